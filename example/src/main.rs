@@ -1,16 +1,17 @@
 use mttp::{
-    http::{HttpRequest, HttpResponse},
-    server::{self, HttpResult, MiddlewareResult},
+    extract::{self, FromParams, Path, Query},
+    http::{HttpError, HttpRequest, HttpResponse},
+    json::JsonValue,
+    server::{self, HttpResult, MiddlewareResult, Next, StaticFiles},
     websocket::{WebSocketMessage, WebSocketMessageRef, WsConnection},
 };
 use std::{
     collections::HashMap,
-    fs,
-    path::PathBuf,
     sync::{
         atomic::{self, AtomicU64},
         Arc,
     },
+    time::Instant,
 };
 
 struct State {
@@ -18,6 +19,12 @@ struct State {
     users: HashMap<String, String>,
 }
 
+// Attached to requests by `mw_auth`
+#[derive(Clone)]
+struct User {
+    name: String,
+}
+
 const WEB_DIR: &str = "web";
 
 fn main() {
@@ -27,27 +34,55 @@ fn main() {
     });
 
     server.get("/hello", hello, vec![]);
-    server.get("/only/with/auth", only_with_auth, vec![mw_auth]);
+    server.group("/only/with", |g| {
+        g.middleware(mw_auth);
+        g.get("/auth", only_with_auth, vec![]);
+    });
     server.get("/person/:id/info/:faktenlage/fake", person, vec![]);
     server.post("/echo", echo, vec![]);
+    server.post("/upload", upload, vec![]);
+    // Uploads are parsed straight from the connection instead of being buffered first
+    server.stream_body("/upload");
+    // Serves everything no other route matched
+    server.static_files("/", StaticFiles::new(WEB_DIR));
 
     server.websocket("/ws/test", ws_handler, vec![]);
 
     server.middleware(mw_log);
+    server.around(mw_timing);
+    server.around(server::compression);
 
     server.error_handler(error_handler);
-    server.not_found_handler(fileserver);
     server.inspector(inspector);
 
-    server.start("127.0.0.1:5000".parse().unwrap()).unwrap();
+    server.start("127.0.0.1:5000").unwrap();
 }
 
-fn error_handler(e: Box<dyn std::error::Error>) -> HttpResponse {
-    println!("handler failed: {e}");
-    HttpResponse::builder()
-        .text("Something went wrong".to_owned())
-        .status(mttp::http::StatusCode::InternalServerError)
-        .build()
+// Renders errors as JSON for API clients and as text for everyone else
+fn error_handler(req: &HttpRequest, e: Box<dyn std::error::Error>) -> HttpResponse {
+    let (status, message) = match e.downcast_ref::<HttpError>() {
+        Some(e) => (e.status, e.message.clone()),
+        None => {
+            println!("{} {} failed: {e}", req.method, req.route);
+            (
+                mttp::http::StatusCode::InternalServerError,
+                "Something went wrong".to_owned(),
+            )
+        }
+    };
+
+    let wants_json = req
+        .headers
+        .get("Accept")
+        .is_some_and(|accept| accept.contains("application/json"));
+
+    let response = HttpResponse::builder().status(status);
+    if wants_json {
+        let error: JsonValue = [("error", message)].into_iter().collect();
+        response.json_value(&error).build()
+    } else {
+        response.text(message).build()
+    }
 }
 
 // Autobahn testsuite compliant websocket server
@@ -80,16 +115,30 @@ fn inspector(_res: &HttpResponse) {}
 
 // Simply logs all incoming requests
 fn mw_log(_: Arc<State>, req: &mut HttpRequest) -> MiddlewareResult {
-    println!("Got request: {}", req.route);
+    println!("Got request from {}: {}", req.peer_addr, req.route);
     MiddlewareResult::Continue
 }
 
+// Measures how long the rest of the chain takes and reports it in a header
+fn mw_timing(_: Arc<State>, req: HttpRequest, next: Next<Arc<State>>) -> HttpResult {
+    let start = Instant::now();
+    let mut response = next.run(req)?;
+
+    response.headers.values.insert(
+        "Server-Timing".to_owned(),
+        format!("total;dur={}", start.elapsed().as_millis()),
+    );
+    Ok(response)
+}
+
 // Ensures an endpoint is protected by auth
 fn mw_auth(state: Arc<State>, req: &mut HttpRequest) -> MiddlewareResult {
     if let Some(token) = req.headers.values.get("auth") {
         if let Some(username) = state.users.get(token) {
             println!("passing {username} with token {token}");
-            req.params.insert("_username".to_owned(), username.clone());
+            req.extensions_mut().insert(User {
+                name: username.clone(),
+            });
             return MiddlewareResult::Continue;
         }
     };
@@ -102,43 +151,56 @@ fn mw_auth(state: Arc<State>, req: &mut HttpRequest) -> MiddlewareResult {
 }
 
 // A basic handler showing how many users have already visited
-fn hello(state: Arc<State>, _: HttpRequest) -> HttpResult {
+// Anything implementing `IntoResponse` can be returned
+fn hello(state: Arc<State>) -> String {
     let count = state.counter.fetch_add(1, atomic::Ordering::SeqCst);
 
     println!("Hello from hello handler");
 
-    Ok(HttpResponse::builder()
-        .text(format!("Hello {}", count))
-        .build())
+    format!("Hello {}", count)
 }
 
 // This handler is only accessable when the user is logged in
 fn only_with_auth(_: Arc<State>, req: HttpRequest) -> HttpResult {
-    let username = req
-        .params
-        .get("_username")
-        .expect("Username not registered in mw");
+    // responds with a 500 if mw_auth didn't run for this route
+    let user = req.extension::<User>()?;
 
     Ok(HttpResponse::builder()
-        .text(format!("Requsted by user: {}", username))
+        .text(format!("Requsted by user: {}", user.name))
         .build())
 }
 
 // Demo on how to get parameters from a route
-fn person(_: Arc<State>, req: HttpRequest) -> HttpResult {
-    let person_id = req.params.get("id").expect("handler param not registered");
-    let Ok(person_id) = person_id.parse::<u64>() else {
-        return Ok(HttpResponse::builder()
-            .status(mttp::http::StatusCode::BadRequest)
-            .text("Invalid person ID format".to_owned())
-            .build());
-    };
+fn person(
+    Path(person): Path<PersonPath>,
+    Query(query): Query<HashMap<String, String>>,
+) -> HttpResult {
+    let greeting = query.get("greeting").map_or("Hello", |x| x.as_str());
 
     Ok(HttpResponse::builder()
-        .text(format!("Hello Person {person_id}"))
+        .text(format!(
+            "{greeting} Person {} ({})",
+            person.id, person.faktenlage
+        ))
         .build())
 }
 
+struct PersonPath {
+    id: u64,
+    faktenlage: String,
+}
+
+// Lets `person` take the route params as a `Path<PersonPath>`,
+// responds with a 400 if the id is not a number
+impl FromParams for PersonPath {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, HttpError> {
+        Ok(PersonPath {
+            id: extract::param(params, "id")?,
+            faktenlage: extract::param(params, "faktenlage")?,
+        })
+    }
+}
+
 // Returns body and headers to the requester
 fn echo(_: Arc<State>, req: HttpRequest) -> HttpResult {
     println!("Hello from echo handler");
@@ -149,21 +211,18 @@ fn echo(_: Arc<State>, req: HttpRequest) -> HttpResult {
         .build())
 }
 
-// Fileserver serving as a fallback
-fn fileserver(_: Arc<State>, req: HttpRequest) -> HttpResult {
-    let safe_route = req.raw_route.replace("../", "");
-    let safe_route = safe_route.strip_prefix('/').unwrap_or(&safe_route);
-
-    let safe_route = if safe_route.is_empty() {
-        "index.html"
-    } else {
-        safe_route
-    };
-
-    let final_path = PathBuf::from(WEB_DIR).join(safe_route);
-
-    match fs::read(final_path) {
-        Ok(file) => Ok(HttpResponse::builder().bytes(file).build()),
-        Err(_) => Ok(HttpResponse::not_found()),
+// Lists the fields and files of a multipart form
+fn upload(mut req: HttpRequest) -> Result<String, HttpError> {
+    let mut summary = String::new();
+    for part in req.multipart()? {
+        let part = part?;
+        match &part.filename {
+            Some(filename) => {
+                summary += &format!("{}: file '{filename}', {} bytes\n", part.name, part.len())
+            }
+            None => summary += &format!("{}: {}\n", part.name, part.text().unwrap_or("")),
+        }
     }
+
+    Ok(summary)
 }
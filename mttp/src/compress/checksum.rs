@@ -0,0 +1,73 @@
+/// CRC-32 as used by gzip (ISO 3309), table driven
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Crc32(u32);
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+impl Crc32 {
+    pub(super) fn new() -> Self {
+        Self(0xffff_ffff)
+    }
+
+    pub(super) fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC_TABLE[((self.0 ^ u32::from(byte)) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(super) fn sum(&self) -> u32 {
+        !self.0
+    }
+}
+
+/// Adler-32 as used by zlib (RFC 1950)
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+const ADLER_MOD: u32 = 65521;
+/// The most bytes that can be summed before `b` could overflow a `u32`
+const ADLER_CHUNK: usize = 5552;
+
+impl Adler32 {
+    pub(super) fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub(super) fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(ADLER_CHUNK) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= ADLER_MOD;
+            self.b %= ADLER_MOD;
+        }
+    }
+
+    pub(super) fn sum(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
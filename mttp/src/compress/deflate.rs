@@ -0,0 +1,549 @@
+use std::{cmp::Reverse, collections::BinaryHeap, mem};
+
+/// How far back matches can reach
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Input compressed as one block, small enough to fall back to a single stored block
+const BLOCK_SIZE: usize = 0xffff;
+
+const HASH_BITS: u32 = 15;
+/// How many earlier positions with the same hash are tried for each match
+const MAX_CHAIN: usize = 64;
+/// A match this long is taken without looking for a longer one
+const NICE_MATCH: usize = 128;
+
+const END_OF_BLOCK: usize = 256;
+const LITERAL_CODES: usize = 286;
+const DISTANCE_CODES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths are sent in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// A raw DEFLATE (RFC 1951) compressor using LZ77 with hash chains and
+/// fixed, dynamic or stored blocks, whichever is smallest.
+///
+/// Compressed bytes collect in an internal buffer, see [`Deflater::take_output`].
+pub(super) struct Deflater {
+    /// Up to a window of already compressed history, followed by the pending input
+    data: Vec<u8>,
+    /// The absolute position of `data[0]` in the whole input
+    base: usize,
+    /// Where the pending input starts in `data`
+    pending: usize,
+    /// The last absolute position + 1 of each hash, 0 if there is none
+    head: Vec<usize>,
+    /// The previous absolute position + 1 with the same hash, indexed by position in the window
+    prev: Vec<usize>,
+    out: BitWriter,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { len: u16, distance: u16 },
+}
+
+impl Deflater {
+    pub(super) fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            base: 0,
+            pending: 0,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW_SIZE],
+            out: BitWriter::default(),
+        }
+    }
+
+    /// Adds input, compressing every full block of it
+    pub(super) fn write(&mut self, input: &[u8]) {
+        self.data.extend_from_slice(input);
+        while self.data.len() - self.pending >= BLOCK_SIZE {
+            self.compress_block(self.pending + BLOCK_SIZE, false);
+        }
+    }
+
+    /// Compresses all pending input and aligns the output to a byte boundary with an empty
+    /// stored block, so everything written so far can be decompressed
+    pub(super) fn flush(&mut self) {
+        if self.pending < self.data.len() {
+            self.compress_block(self.data.len(), false);
+        }
+        self.out.bits(0, 3);
+        self.out.align();
+        self.out.bytes(&[0, 0, 0xff, 0xff]);
+    }
+
+    /// Compresses all pending input as the final block
+    pub(super) fn finish(&mut self) {
+        self.compress_block(self.data.len(), true);
+        self.out.align();
+    }
+
+    /// The complete bytes of compressed output produced so far
+    pub(super) fn take_output(&mut self) -> Vec<u8> {
+        mem::take(&mut self.out.out)
+    }
+
+    fn compress_block(&mut self, end: usize, last: bool) {
+        let start = self.pending;
+        let mut tokens = Vec::new();
+
+        let mut i = start;
+        while i < end {
+            match self.longest_match(i, end) {
+                Some((len, distance)) => {
+                    tokens.push(Token::Match {
+                        len: len as u16,
+                        distance: distance as u16,
+                    });
+                    for position in i..i + len {
+                        self.insert(position);
+                    }
+                    i += len;
+                }
+                None => {
+                    tokens.push(Token::Literal(self.data[i]));
+                    self.insert(i);
+                    i += 1;
+                }
+            }
+        }
+
+        write_block(&mut self.out, &tokens, &self.data[start..end], last);
+        self.pending = end;
+
+        // Only a window of history is needed for later matches
+        if self.pending > WINDOW_SIZE {
+            let drop = self.pending - WINDOW_SIZE;
+            self.data.drain(..drop);
+            self.base += drop;
+            self.pending -= drop;
+        }
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let bytes = &self.data[i..i + MIN_MATCH];
+        let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let hash = self.hash(i);
+        let position = self.base + i;
+        self.prev[position % WINDOW_SIZE] = self.head[hash];
+        self.head[hash] = position + 1;
+    }
+
+    /// The longest earlier match for the input at `i` that ends before `end`
+    fn longest_match(&self, i: usize, end: usize) -> Option<(usize, usize)> {
+        let max_len = (end - i).min(MAX_MATCH);
+        if max_len < MIN_MATCH {
+            return None;
+        }
+
+        let position = self.base + i;
+        let mut candidate = self.head[self.hash(i)];
+        let mut best: Option<(usize, usize)> = None;
+
+        for _ in 0..MAX_CHAIN {
+            // Positions are stored + 1, so 0 ends the chain
+            let Some(earlier) = candidate.checked_sub(1) else {
+                break;
+            };
+            let distance = position - earlier;
+            if earlier < self.base || distance > WINDOW_SIZE {
+                break;
+            }
+
+            let offset = earlier - self.base;
+            let best_len = best.map_or(MIN_MATCH - 1, |(len, _)| len);
+            // Cheap check of the byte that would make this match longer than the best one
+            if self.data[offset + best_len] == self.data[i + best_len] {
+                let len = self.data[offset..offset + max_len]
+                    .iter()
+                    .zip(&self.data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best = Some((len, distance));
+                    if len >= NICE_MATCH.min(max_len) {
+                        break;
+                    }
+                }
+            }
+
+            let next = self.prev[earlier % WINDOW_SIZE];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+
+        best
+    }
+}
+
+#[derive(Debug, Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `count` bits of `value`, least significant first
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which are packed starting with their most significant bit
+    fn code(&mut self, codes: &[(u16, u8)], symbol: usize) {
+        let (code, len) = codes[symbol];
+        self.bits(u32::from(code), u32::from(len));
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+            self.buffer = 0;
+            self.count = 0;
+        }
+    }
+
+    /// Only allowed when aligned
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+}
+
+fn length_symbol(len: u16) -> usize {
+    LENGTH_BASE.iter().rposition(|base| *base <= len).unwrap()
+}
+
+fn distance_symbol(distance: u16) -> usize {
+    DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base <= distance)
+        .unwrap()
+}
+
+/// A run-length encoded entry of the code lengths of a dynamic block
+#[derive(Debug, Clone, Copy)]
+struct CodeLength {
+    symbol: usize,
+    extra: u32,
+    extra_bits: u32,
+}
+
+fn write_block(out: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let mut literal_freqs = [0_u32; LITERAL_CODES];
+    let mut distance_freqs = [0_u32; DISTANCE_CODES];
+    literal_freqs[END_OF_BLOCK] = 1;
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literal_freqs[usize::from(byte)] += 1,
+            Token::Match { len, distance } => {
+                literal_freqs[257 + length_symbol(len)] += 1;
+                distance_freqs[distance_symbol(distance)] += 1;
+            }
+        }
+    }
+
+    let literal_lengths = huffman_lengths(&literal_freqs, 15);
+    let distance_lengths = huffman_lengths(&distance_freqs, 15);
+
+    let literals = literal_lengths.iter().rposition(|len| *len > 0).unwrap() + 1;
+    let distances = distance_lengths.iter().rposition(|len| *len > 0).unwrap() + 1;
+    let code_lengths = run_length_encode(
+        &[
+            &literal_lengths[..literals.max(257)],
+            &distance_lengths[..distances],
+        ]
+        .concat(),
+    );
+
+    let mut code_length_freqs = [0_u32; 19];
+    for entry in &code_lengths {
+        code_length_freqs[entry.symbol] += 1;
+    }
+    let code_length_lengths = huffman_lengths(&code_length_freqs, 7);
+    let code_length_count = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&symbol| code_length_lengths[symbol] > 0)
+        .unwrap()
+        .max(3)
+        + 1;
+
+    let (fixed_literals, fixed_distances) = fixed_lengths();
+    let dynamic_cost = 3
+        + 14
+        + 3 * code_length_count
+        + code_lengths
+            .iter()
+            .map(|entry| usize::from(code_length_lengths[entry.symbol]) + entry.extra_bits as usize)
+            .sum::<usize>()
+        + data_cost(tokens, &literal_lengths, &distance_lengths);
+    let fixed_cost = 3 + data_cost(tokens, &fixed_literals, &fixed_distances);
+    // The header, up to 7 bits of padding, and the length with its complement
+    let stored_cost = 3 + 7 + 32 + 8 * raw.len();
+
+    out.bits(u32::from(last), 1);
+
+    if stored_cost < dynamic_cost.min(fixed_cost) {
+        out.bits(0b00, 2);
+        out.align();
+        let len = raw.len() as u16;
+        out.bytes(&len.to_le_bytes());
+        out.bytes(&(!len).to_le_bytes());
+        out.bytes(raw);
+    } else if fixed_cost <= dynamic_cost {
+        out.bits(0b01, 2);
+        write_tokens(
+            out,
+            tokens,
+            &canonical_codes(&fixed_literals),
+            &canonical_codes(&fixed_distances),
+        );
+    } else {
+        out.bits(0b10, 2);
+        out.bits((literals.max(257) - 257) as u32, 5);
+        out.bits((distances - 1) as u32, 5);
+        out.bits((code_length_count - 4) as u32, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+            out.bits(u32::from(code_length_lengths[symbol]), 3);
+        }
+
+        let code_length_codes = canonical_codes(&code_length_lengths);
+        for entry in &code_lengths {
+            out.code(&code_length_codes, entry.symbol);
+            out.bits(entry.extra, entry.extra_bits);
+        }
+
+        write_tokens(
+            out,
+            tokens,
+            &canonical_codes(&literal_lengths),
+            &canonical_codes(&distance_lengths),
+        );
+    }
+}
+
+fn write_tokens(
+    out: &mut BitWriter,
+    tokens: &[Token],
+    literal_codes: &[(u16, u8)],
+    distance_codes: &[(u16, u8)],
+) {
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => out.code(literal_codes, usize::from(byte)),
+            Token::Match { len, distance } => {
+                let symbol = length_symbol(len);
+                out.code(literal_codes, 257 + symbol);
+                out.bits(
+                    u32::from(len - LENGTH_BASE[symbol]),
+                    u32::from(LENGTH_EXTRA[symbol]),
+                );
+
+                let symbol = distance_symbol(distance);
+                out.code(distance_codes, symbol);
+                out.bits(
+                    u32::from(distance - DISTANCE_BASE[symbol]),
+                    u32::from(DISTANCE_EXTRA[symbol]),
+                );
+            }
+        }
+    }
+    out.code(literal_codes, END_OF_BLOCK);
+}
+
+/// The bits needed for the tokens and the end of block with the given code lengths
+fn data_cost(tokens: &[Token], literal_lengths: &[u8], distance_lengths: &[u8]) -> usize {
+    let cost = tokens
+        .iter()
+        .map(|token| match *token {
+            Token::Literal(byte) => usize::from(literal_lengths[usize::from(byte)]),
+            Token::Match { len, distance } => {
+                let length = length_symbol(len);
+                let distance = distance_symbol(distance);
+                usize::from(literal_lengths[257 + length])
+                    + usize::from(LENGTH_EXTRA[length])
+                    + usize::from(distance_lengths[distance])
+                    + usize::from(DISTANCE_EXTRA[distance])
+            }
+        })
+        .sum::<usize>();
+
+    cost + usize::from(literal_lengths[END_OF_BLOCK])
+}
+
+/// The code lengths of fixed Huffman blocks
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let literals = (0..288)
+        .map(|symbol| match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        })
+        .collect();
+    (literals, vec![5; 30])
+}
+
+/// Encodes code lengths with the repeat codes 16 (previous length), 17 and 18 (zeros)
+fn run_length_encode(lengths: &[u8]) -> Vec<CodeLength> {
+    let entry = |symbol, extra, extra_bits| CodeLength {
+        symbol,
+        extra,
+        extra_bits,
+    };
+    let mut encoded = Vec::new();
+
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|l| **l == len).count();
+
+        if len == 0 && run >= 3 {
+            let run = run.min(138);
+            match run {
+                3..=10 => encoded.push(entry(17, run as u32 - 3, 3)),
+                _ => encoded.push(entry(18, run as u32 - 11, 7)),
+            }
+            i += run;
+        } else if len != 0 && run >= 4 {
+            encoded.push(entry(usize::from(len), 0, 0));
+            let mut repeats = run - 1;
+            while repeats >= 3 {
+                let n = repeats.min(6);
+                encoded.push(entry(16, n as u32 - 3, 2));
+                repeats -= n;
+            }
+            for _ in 0..repeats {
+                encoded.push(entry(usize::from(len), 0, 0));
+            }
+            i += run;
+        } else {
+            encoded.push(entry(usize::from(len), 0, 0));
+            i += 1;
+        }
+    }
+
+    encoded
+}
+
+/// Huffman code lengths for `freqs` that are at most `limit` bits long.
+///
+/// At least two symbols get a code, as some decoders reject incomplete codes.
+fn huffman_lengths(freqs: &[u32], limit: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    for symbol in 0..freqs.len() {
+        if freqs.iter().filter(|freq| **freq > 0).count() >= 2 {
+            break;
+        }
+        if freqs[symbol] == 0 {
+            freqs[symbol] = 1;
+        }
+    }
+
+    loop {
+        let lengths = tree_depths(&freqs);
+        if lengths.iter().all(|len| *len <= limit) {
+            return lengths;
+        }
+        // Flattening the distribution makes the tree shallower, eventually balanced
+        for freq in freqs.iter_mut().filter(|freq| **freq > 0) {
+            *freq = (*freq >> 1).max(1);
+        }
+    }
+}
+
+/// The depth of each symbol in a Huffman tree for `freqs`, 0 for unused symbols
+fn tree_depths(freqs: &[u32]) -> Vec<u8> {
+    // Leaves are the symbols, inner nodes are appended after them
+    let mut parents = vec![usize::MAX; freqs.len()];
+    let mut heap = freqs
+        .iter()
+        .enumerate()
+        .filter(|(_, freq)| **freq > 0)
+        .map(|(symbol, freq)| Reverse((u64::from(*freq), symbol)))
+        .collect::<BinaryHeap<_>>();
+
+    while heap.len() > 1 {
+        let Reverse((a_freq, a)) = heap.pop().unwrap();
+        let Reverse((b_freq, b)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((a_freq + b_freq, node)));
+    }
+
+    // Parents are always created after their children, so walking backwards
+    // visits a node's parent before the node
+    let mut depths = vec![0_u8; parents.len()];
+    for node in (0..parents.len()).rev() {
+        if parents[node] != usize::MAX {
+            depths[node] = depths[parents[node]].saturating_add(1);
+        }
+    }
+
+    depths.truncate(freqs.len());
+    depths
+}
+
+/// The canonical code of each symbol (RFC 1951 section 3.2.2), bit reversed for writing
+fn canonical_codes(lengths: &[u8]) -> Vec<(u16, u8)> {
+    let mut counts = [0_u16; 16];
+    for &len in lengths {
+        counts[usize::from(len)] += 1;
+    }
+    counts[0] = 0;
+
+    let mut next = [0_u16; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return (0, 0);
+            }
+            let code = next[usize::from(len)];
+            next[usize::from(len)] += 1;
+            (code.reverse_bits() >> (16 - len), len)
+        })
+        .collect()
+}
@@ -0,0 +1,134 @@
+mod checksum;
+mod deflate;
+
+#[cfg(test)]
+mod test;
+
+use checksum::{Adler32, Crc32};
+use deflate::Deflater;
+use std::io::{self, Write};
+
+/// The container around the compressed data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Plain DEFLATE data (RFC 1951)
+    Raw,
+    /// zlib (RFC 1950), what HTTP calls `deflate`
+    Zlib,
+    /// gzip (RFC 1952)
+    Gzip,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Checksum {
+    None,
+    Adler32(Adler32),
+    Crc32(Crc32),
+}
+
+/// Compresses everything written to it into `inner`.
+///
+/// [`flush`](Write::flush) makes all data written so far decompressible, at the cost of a
+/// few bytes, which is what chunked responses need. [`Encoder::finish`] has to be called
+/// to write the end of the stream.
+pub struct Encoder<W: Write> {
+    inner: W,
+    deflater: Deflater,
+    format: Format,
+    checksum: Checksum,
+    /// The input length modulo 2^32, as stored by gzip
+    len: u32,
+    header_written: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W, format: Format) -> Self {
+        let checksum = match format {
+            Format::Raw => Checksum::None,
+            Format::Zlib => Checksum::Adler32(Adler32::new()),
+            Format::Gzip => Checksum::Crc32(Crc32::new()),
+        };
+
+        Self {
+            inner,
+            deflater: Deflater::new(),
+            format,
+            checksum,
+            len: 0,
+            header_written: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Compresses the remaining input, writes the trailer and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.deflater.finish();
+        self.write_output()?;
+
+        match self.checksum {
+            Checksum::None => {}
+            Checksum::Adler32(adler) => self.inner.write_all(&adler.sum().to_be_bytes())?,
+            Checksum::Crc32(crc) => {
+                self.inner.write_all(&crc.sum().to_le_bytes())?;
+                self.inner.write_all(&self.len.to_le_bytes())?;
+            }
+        }
+
+        Ok(self.inner)
+    }
+
+    /// Writes the compressed output produced so far, preceded by the header the first time
+    fn write_output(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.header_written = true;
+            match self.format {
+                Format::Raw => {}
+                // 32 KiB window, default compression, no dictionary
+                Format::Zlib => self.inner.write_all(&[0x78, 0x9c])?,
+                // No modification time or file name, unknown OS
+                Format::Gzip => self
+                    .inner
+                    .write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff])?,
+            }
+        }
+
+        self.inner.write_all(&self.deflater.take_output())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.checksum {
+            Checksum::None => {}
+            Checksum::Adler32(adler) => adler.update(buf),
+            Checksum::Crc32(crc) => crc.update(buf),
+        }
+        self.len = self.len.wrapping_add(buf.len() as u32);
+
+        self.deflater.write(buf);
+        self.write_output()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflater.flush();
+        self.write_output()?;
+        self.inner.flush()
+    }
+}
+
+/// Compresses `data` in one go
+pub fn compress(data: &[u8], format: Format) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new(), format);
+    encoder
+        .write_all(data)
+        .expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
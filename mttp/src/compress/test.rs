@@ -0,0 +1,321 @@
+use super::{
+    checksum::{Adler32, Crc32},
+    compress, Encoder, Format,
+};
+use std::io::Write;
+
+/// A minimal DEFLATE decoder to check the encoder's output against
+struct Inflater<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit: u32,
+}
+
+/// A canonical Huffman code as the number of codes per length and the symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols = Vec::new();
+        for len in 1..16 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, l)| **l == len) {
+                symbols.push(symbol as u16);
+            }
+        }
+        Self { counts, symbols }
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+impl<'a> Inflater<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            bit: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data[self.position];
+            value |= u32::from((byte >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        value
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> usize {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for len in 1..16 {
+            code |= self.bits(1) as i32;
+            let count = i32::from(huffman.counts[len]);
+            if code - first < count {
+                return usize::from(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        panic!("invalid Huffman code");
+    }
+
+    /// Decodes a whole raw DEFLATE stream, returning the data and the bytes consumed
+    fn inflate(mut self) -> (Vec<u8>, usize) {
+        let mut out: Vec<u8> = Vec::new();
+
+        loop {
+            let last = self.bits(1) == 1;
+            match self.bits(2) {
+                0 => {
+                    if self.bit > 0 {
+                        self.bit = 0;
+                        self.position += 1;
+                    }
+                    let len = u16::from_le_bytes([
+                        self.data[self.position],
+                        self.data[self.position + 1],
+                    ]);
+                    let nlen = u16::from_le_bytes([
+                        self.data[self.position + 2],
+                        self.data[self.position + 3],
+                    ]);
+                    assert_eq!(len, !nlen);
+                    self.position += 4;
+                    let end = self.position + usize::from(len);
+                    out.extend_from_slice(&self.data[self.position..end]);
+                    self.position = end;
+                }
+                1 => {
+                    let literals = (0..288)
+                        .map(|symbol| match symbol {
+                            0..=143 => 8,
+                            144..=255 => 9,
+                            256..=279 => 7,
+                            _ => 8,
+                        })
+                        .collect::<Vec<_>>();
+                    self.codes(&mut out, &Huffman::new(&literals), &Huffman::new(&[5; 30]));
+                }
+                2 => {
+                    let literals = self.bits(5) as usize + 257;
+                    let distances = self.bits(5) as usize + 1;
+                    let code_lengths = self.bits(4) as usize + 4;
+
+                    let mut lengths = [0; 19];
+                    for &symbol in &[
+                        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+                    ][..code_lengths]
+                    {
+                        lengths[symbol] = self.bits(3) as u8;
+                    }
+                    let code_length_code = Huffman::new(&lengths);
+
+                    let mut lengths = Vec::new();
+                    while lengths.len() < literals + distances {
+                        match self.decode(&code_length_code) {
+                            len @ 0..=15 => lengths.push(len as u8),
+                            16 => {
+                                let previous = *lengths.last().unwrap();
+                                let repeat = 3 + self.bits(2);
+                                lengths.extend((0..repeat).map(|_| previous));
+                            }
+                            17 => {
+                                let repeat = 3 + self.bits(3);
+                                lengths.extend((0..repeat).map(|_| 0));
+                            }
+                            _ => {
+                                let repeat = 11 + self.bits(7);
+                                lengths.extend((0..repeat).map(|_| 0));
+                            }
+                        }
+                    }
+                    assert_eq!(lengths.len(), literals + distances);
+
+                    self.codes(
+                        &mut out,
+                        &Huffman::new(&lengths[..literals]),
+                        &Huffman::new(&lengths[literals..]),
+                    );
+                }
+                _ => panic!("invalid block type"),
+            }
+
+            if last {
+                let consumed = self.position + usize::from(self.bit > 0);
+                return (out, consumed);
+            }
+        }
+    }
+
+    fn codes(&mut self, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) {
+        loop {
+            match self.decode(literals) {
+                literal @ 0..=255 => out.push(literal as u8),
+                256 => return,
+                symbol => {
+                    let symbol = symbol - 257;
+                    let len =
+                        usize::from(LENGTH_BASE[symbol]) + self.bits(LENGTH_EXTRA[symbol]) as usize;
+                    let symbol = self.decode(distances);
+                    let distance = usize::from(DISTANCE_BASE[symbol])
+                        + self.bits(DISTANCE_EXTRA[symbol]) as usize;
+                    assert!(distance <= out.len(), "distance too far back");
+                    for _ in 0..len {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn inflate(data: &[u8]) -> Vec<u8> {
+    let (out, consumed) = Inflater::new(data).inflate();
+    assert_eq!(consumed, data.len(), "trailing data after the final block");
+    out
+}
+
+/// Pseudo random bytes, which don't compress
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn text(len: usize) -> Vec<u8> {
+    let words = [
+        "lorem", "ipsum", "dolor", "sit", "amet", "{\"id\":", "</div>", "\n",
+    ];
+    let mut state = 7_u32;
+    let mut text = Vec::with_capacity(len + 8);
+    while text.len() < len {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        text.extend_from_slice(words[(state >> 16) as usize % words.len()].as_bytes());
+        text.push(b' ');
+    }
+    text.truncate(len);
+    text
+}
+
+#[test]
+fn test_checksums() {
+    let mut crc = Crc32::new();
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(crc.sum(), 0xcbf4_3926);
+    assert_eq!(Crc32::new().sum(), 0);
+
+    let mut adler = Adler32::new();
+    adler.update(b"Wikipedia");
+    assert_eq!(adler.sum(), 0x11e6_0398);
+
+    // Long enough to need the modulo in between
+    let mut adler = Adler32::new();
+    adler.update(&[0xff; 100_000]);
+    let (mut a, mut b) = (1_u64, 0_u64);
+    for _ in 0..100_000 {
+        a = (a + 0xff) % 65521;
+        b = (b + a) % 65521;
+    }
+    assert_eq!(u64::from(adler.sum()), (b << 16) | a);
+}
+
+#[test]
+fn test_deflate_round_trip() {
+    let inputs = [
+        Vec::new(),
+        b"a".to_vec(),
+        b"abcabcabcabcabcabc".to_vec(),
+        vec![0; 100_000],
+        text(300_000),
+        noise(200_000),
+        [text(50_000), noise(50_000), text(50_000)].concat(),
+    ];
+
+    for input in inputs {
+        let compressed = compress(&input, Format::Raw);
+        assert_eq!(inflate(&compressed), input, "length {}", input.len());
+    }
+
+    let text = text(100_000);
+    assert!(compress(&text, Format::Raw).len() < text.len() / 3);
+    let noise = noise(100_000);
+    // Stored blocks only add a few bytes
+    assert!(compress(&noise, Format::Raw).len() < noise.len() + 20);
+}
+
+#[test]
+fn test_gzip_and_zlib() {
+    let input = text(10_000);
+
+    let gzip = compress(&input, Format::Gzip);
+    assert_eq!(gzip[..4], [0x1f, 0x8b, 8, 0]);
+    let (body, trailer) = gzip[10..].split_at(gzip.len() - 18);
+    assert_eq!(inflate(body), input);
+    let mut crc = Crc32::new();
+    crc.update(&input);
+    assert_eq!(trailer[..4], crc.sum().to_le_bytes());
+    assert_eq!(trailer[4..], (input.len() as u32).to_le_bytes());
+
+    let zlib = compress(&input, Format::Zlib);
+    assert_eq!((u16::from(zlib[0]) << 8 | u16::from(zlib[1])) % 31, 0);
+    let (body, trailer) = zlib[2..].split_at(zlib.len() - 6);
+    assert_eq!(inflate(body), input);
+    let mut adler = Adler32::new();
+    adler.update(&input);
+    assert_eq!(trailer, adler.sum().to_be_bytes());
+}
+
+#[test]
+fn test_encoder_flush() {
+    let input = text(20_000);
+    let mut encoder = Encoder::new(Vec::new(), Format::Raw);
+
+    for chunk in input.chunks(3000) {
+        encoder.write_all(chunk).unwrap();
+        encoder.flush().unwrap();
+
+        // After a flush everything written so far can be decoded
+        let flushed = encoder.get_ref();
+        assert_eq!(flushed[flushed.len() - 4..], [0, 0, 0xff, 0xff]);
+    }
+
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(inflate(&compressed), input);
+}
@@ -0,0 +1,357 @@
+use crate::http::{
+    consts::headers::{AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
+    HeaderMap, HttpError, HttpRequest, HttpResponse, IntoResponse, QueryParams,
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    error::Error,
+    ops::{Deref, DerefMut},
+    str::FromStr,
+    sync::Arc,
+};
+
+#[cfg(test)]
+mod test;
+
+/// Something a handler can take as an argument, built from the request before the handler runs.
+///
+/// Handlers may take any combination of extractors, they are extracted in argument order.
+/// A failed extraction never reaches the handler, the error is passed to the error handler instead.
+pub trait FromRequest<S>: Sized {
+    fn from_request(req: &mut HttpRequest, state: &Arc<S>) -> Result<Self, HttpError>;
+
+    /// Used when extracting `Option<Self>`, should be `None` only if the value isn't there at all.
+    ///
+    /// A value that is there but invalid still fails the request.
+    /// By default every failure does, for extractors that can't be absent.
+    fn from_request_optional(
+        req: &mut HttpRequest,
+        state: &Arc<S>,
+    ) -> Result<Option<Self>, HttpError> {
+        Self::from_request(req, state).map(Some)
+    }
+}
+
+/// Builds a value from a set of named string parameters, used by [`Path`], [`Query`] and [`Form`]
+pub trait FromParams: Sized {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, HttpError>;
+}
+
+/// Parses a JSON document, used by [`Json`]
+pub trait FromJson: Sized {
+    fn from_json(json: &str) -> Result<Self, Box<dyn Error>>;
+}
+
+/// Serializes a value to a JSON document, used when returning [`Json`] from a handler
+pub trait ToJson {
+    fn to_json(&self) -> String;
+}
+
+/// A header that can be extracted with [`Header`]
+pub trait TypedHeader: Sized {
+    const NAME: &'static str;
+
+    fn parse(value: &str) -> Result<Self, HttpError>;
+}
+
+/// Parses the parameter `name`, failing with a 400 if it is missing or invalid.
+///
+/// Meant for implementing [`FromParams`] on your own types.
+pub fn param<T: FromStr>(params: &HashMap<String, String>, name: &str) -> Result<T, HttpError>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = params
+        .get(name)
+        .ok_or_else(|| HttpError::bad_request(format!("Missing parameter '{name}'")))?;
+
+    value
+        .parse()
+        .map_err(|e| HttpError::bad_request(format!("Invalid parameter '{name}': {e}")))
+}
+
+impl FromParams for HashMap<String, String> {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, HttpError> {
+        Ok(params.clone())
+    }
+}
+
+/// Single values can be extracted directly if there is exactly one parameter
+macro_rules! impl_from_params_single {
+    ($($ty:ty),*) => {
+        $(
+            impl FromParams for $ty {
+                fn from_params(params: &HashMap<String, String>) -> Result<Self, HttpError> {
+                    let mut values = params.iter();
+                    match (values.next(), values.next()) {
+                        (Some((name, _)), None) => param(params, name),
+                        _ => Err(HttpError::internal(format!(
+                            "Expected exactly one parameter, got {}",
+                            params.len()
+                        ))),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_params_single!(
+    String, bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+
+/// The shared server state
+#[derive(Debug)]
+pub struct State<S>(pub Arc<S>);
+
+impl<S> Deref for State<S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<S> FromRequest<S> for State<S> {
+    fn from_request(_: &mut HttpRequest, state: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(State(state.clone()))
+    }
+}
+
+// Keeps the classic `fn(Arc<State>, HttpRequest)` handler signature working
+impl<S> FromRequest<S> for Arc<S> {
+    fn from_request(_: &mut HttpRequest, state: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(state.clone())
+    }
+}
+
+/// Takes the whole request, so it has to be the last argument of a handler.
+///
+/// Extractors after it only see an empty request.
+impl<S> FromRequest<S> for HttpRequest {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(std::mem::take(req))
+    }
+}
+
+/// Passes `None` if the request doesn't carry `T` at all, a malformed `T` still fails the request
+impl<S, T: FromRequest<S>> FromRequest<S> for Option<T> {
+    fn from_request(req: &mut HttpRequest, state: &Arc<S>) -> Result<Self, HttpError> {
+        T::from_request_optional(req, state)
+    }
+}
+
+/// Whether the request came without a body
+fn no_body(req: &HttpRequest) -> bool {
+    req.body.as_deref().is_none_or(<[u8]>::is_empty)
+}
+
+macro_rules! impl_wrapper {
+    ($name:ident $(<$t:ident>)?, $target:ty) => {
+        impl$(<$t>)? Deref for $name$(<$t>)? {
+            type Target = $target;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl$(<$t>)? DerefMut for $name$(<$t>)? {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+    };
+}
+
+/// The parameters captured by the route pattern, see [`FromParams`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path<T>(pub T);
+impl_wrapper!(Path<T>, T);
+
+impl<S, T: FromParams> FromRequest<S> for Path<T> {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        T::from_params(&req.path_params).map(Path)
+    }
+}
+
+/// The query string parameters, see [`FromParams`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query<T>(pub T);
+impl_wrapper!(Query<T>, T);
+
+impl<S, T: FromParams> FromRequest<S> for Query<T> {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        T::from_params(&req.query.to_map()).map(Query)
+    }
+
+    /// `None` without a query string
+    fn from_request_optional(
+        req: &mut HttpRequest,
+        state: &Arc<S>,
+    ) -> Result<Option<Self>, HttpError> {
+        if req.query.is_empty() {
+            return Ok(None);
+        }
+        Self::from_request(req, state).map(Some)
+    }
+}
+
+/// All query string parameters, including repeated keys
+impl<S> FromRequest<S> for QueryParams {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(req.query.clone())
+    }
+}
+
+/// An `application/x-www-form-urlencoded` body, see [`FromParams`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form<T>(pub T);
+impl_wrapper!(Form<T>, T);
+
+impl<S, T: FromParams> FromRequest<S> for Form<T> {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        T::from_params(&req.form()?.to_map()).map(Form)
+    }
+
+    /// `None` without a body
+    fn from_request_optional(
+        req: &mut HttpRequest,
+        state: &Arc<S>,
+    ) -> Result<Option<Self>, HttpError> {
+        if no_body(req) {
+            return Ok(None);
+        }
+        Self::from_request(req, state).map(Some)
+    }
+}
+
+/// An `application/json` body, see [`FromJson`].
+///
+/// Can also be returned from handlers, see [`ToJson`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+impl_wrapper!(Json<T>, T);
+
+impl<S, T: FromJson> FromRequest<S> for Json<T> {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        req.expect_content_type("application/json")?;
+        let body = req.body_text()?;
+        T::from_json(body)
+            .map(Json)
+            .map_err(|e| HttpError::bad_request(format!("Invalid JSON body: {e}")))
+    }
+
+    /// `None` without a body
+    fn from_request_optional(
+        req: &mut HttpRequest,
+        state: &Arc<S>,
+    ) -> Result<Option<Self>, HttpError> {
+        if no_body(req) {
+            return Ok(None);
+        }
+        Self::from_request(req, state).map(Some)
+    }
+}
+
+impl<T: ToJson> IntoResponse for Json<T> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::builder().json(self.0.to_json()).build()
+    }
+}
+
+/// A single typed header, fails with a 400 if it is missing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header<H>(pub H);
+impl_wrapper!(Header<H>, H);
+
+impl<S, H: TypedHeader> FromRequest<S> for Header<H> {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        let value = req
+            .headers
+            .get(H::NAME)
+            .ok_or_else(|| HttpError::bad_request(format!("Missing header '{}'", H::NAME)))?;
+
+        H::parse(value).map(Header)
+    }
+
+    /// `None` if the header is missing
+    fn from_request_optional(req: &mut HttpRequest, _: &Arc<S>) -> Result<Option<Self>, HttpError> {
+        req.headers
+            .get(H::NAME)
+            .map(|value| H::parse(value).map(Header))
+            .transpose()
+    }
+}
+
+/// Implements [`TypedHeader`] for a header whose value is used as is
+macro_rules! string_header {
+    ($name:ident, $header:expr) => {
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct $name(pub String);
+
+        impl TypedHeader for $name {
+            const NAME: &'static str = $header;
+
+            fn parse(value: &str) -> Result<Self, HttpError> {
+                Ok(Self(value.to_owned()))
+            }
+        }
+    };
+}
+
+string_header!(ContentType, CONTENT_TYPE);
+string_header!(UserAgent, USER_AGENT);
+string_header!(Authorization, AUTHORIZATION);
+
+/// All request headers
+impl<S> FromRequest<S> for HeaderMap {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(req.headers.clone())
+    }
+}
+
+/// The cookies sent with the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookies(pub HashMap<String, String>);
+impl_wrapper!(Cookies, HashMap<String, String>);
+
+impl<S> FromRequest<S> for Cookies {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(Cookies(
+            req.headers
+                .cookies()
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect(),
+        ))
+    }
+}
+
+/// The raw request body, empty if there is none
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bytes(pub Vec<u8>);
+impl_wrapper!(Bytes, Vec<u8>);
+
+impl<S> FromRequest<S> for Bytes {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        Ok(Bytes(req.body.take().unwrap_or_default()))
+    }
+}
+
+/// A value attached by a middleware, see [`HttpRequest::extension`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension<T>(pub T);
+impl_wrapper!(Extension<T>, T);
+
+impl<S, T: Any + Clone + Send + Sync> FromRequest<S> for Extension<T> {
+    fn from_request(req: &mut HttpRequest, _: &Arc<S>) -> Result<Self, HttpError> {
+        req.extension::<T>().cloned().map(Extension)
+    }
+
+    /// `None` if no middleware attached a `T`
+    fn from_request_optional(req: &mut HttpRequest, _: &Arc<S>) -> Result<Option<Self>, HttpError> {
+        Ok(req.extensions().get::<T>().cloned().map(Extension))
+    }
+}
@@ -0,0 +1,269 @@
+use super::{
+    Authorization, Bytes, Cookies, Extension, Form, FromJson, FromParams, FromRequest, Header,
+    Json, Path, Query, State, ToJson,
+};
+use crate::{
+    http::{HttpError, HttpRequest, HttpResponse, IntoResponse, StatusCode},
+    server::{Handler, HttpResult},
+};
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+fn request(headers: &[(&str, &str)], body: &str) -> HttpRequest {
+    let mut req = HttpRequest::default();
+    for (k, v) in headers {
+        req.headers.values.insert(k.to_string(), v.to_string());
+    }
+    req.body = Some(body.as_bytes().to_vec());
+    req
+}
+
+fn extract<T: FromRequest<u32>>(req: &mut HttpRequest) -> Result<T, StatusCode> {
+    T::from_request(req, &Arc::new(7)).map_err(|e| e.status)
+}
+
+#[derive(Debug, PartialEq)]
+struct Pagination {
+    page: u32,
+    per_page: u32,
+}
+
+impl FromParams for Pagination {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, HttpError> {
+        Ok(Pagination {
+            page: super::param(params, "page")?,
+            per_page: super::param(params, "per_page")?,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Raw(String);
+
+impl FromJson for Raw {
+    fn from_json(json: &str) -> Result<Self, Box<dyn Error>> {
+        match json.starts_with('{') {
+            true => Ok(Raw(json.to_owned())),
+            false => Err("not an object".into()),
+        }
+    }
+}
+
+#[test]
+fn test_path_and_query() {
+    let mut req = HttpRequest::default();
+    req.path_params.insert("id".to_owned(), "5".to_owned());
+    req.query.insert("page".to_owned(), "2".to_owned());
+    req.query.insert("per_page".to_owned(), "50".to_owned());
+
+    assert_eq!(extract::<Path<u64>>(&mut req), Ok(Path(5)));
+    assert_eq!(
+        extract::<Query<Pagination>>(&mut req),
+        Ok(Query(Pagination {
+            page: 2,
+            per_page: 50
+        }))
+    );
+
+    req.path_params.insert("id".to_owned(), "five".to_owned());
+    assert_eq!(extract::<Path<u64>>(&mut req), Err(StatusCode::BadRequest));
+
+    req.query.remove("per_page");
+    assert_eq!(
+        extract::<Query<Pagination>>(&mut req),
+        Err(StatusCode::BadRequest)
+    );
+}
+
+#[test]
+fn test_body_extractors() {
+    let mut req = request(
+        &[("Content-Type", "application/x-www-form-urlencoded")],
+        "page=1&per_page=10",
+    );
+    assert_eq!(
+        extract::<Form<Pagination>>(&mut req).map(|x| x.0),
+        Ok(Pagination {
+            page: 1,
+            per_page: 10
+        })
+    );
+    assert_eq!(
+        extract::<Json<Raw>>(&mut req),
+        Err(StatusCode::UnsupportedMediaType)
+    );
+
+    let mut req = request(&[("Content-Type", "application/json; charset=utf-8")], "{}");
+    assert_eq!(
+        extract::<Json<Raw>>(&mut req),
+        Ok(Json(Raw("{}".to_owned())))
+    );
+
+    let mut req = request(&[("Content-Type", "application/json")], "[]");
+    assert_eq!(extract::<Json<Raw>>(&mut req), Err(StatusCode::BadRequest));
+
+    assert_eq!(extract::<Bytes>(&mut req), Ok(Bytes(b"[]".to_vec())));
+    assert_eq!(extract::<Bytes>(&mut req), Ok(Bytes(Vec::new())));
+}
+
+#[test]
+fn test_header_extractors() {
+    let mut req = request(&[("Authorization", "Bearer x"), ("Cookie", "a=1; b=2")], "");
+
+    assert_eq!(
+        extract::<Header<Authorization>>(&mut req),
+        Ok(Header(Authorization("Bearer x".to_owned())))
+    );
+    assert_eq!(
+        extract::<Cookies>(&mut req).map(|c| c.get("b").cloned()),
+        Ok(Some("2".to_owned()))
+    );
+
+    req.headers.values.clear();
+    assert_eq!(
+        extract::<Header<Authorization>>(&mut req),
+        Err(StatusCode::BadRequest)
+    );
+    assert_eq!(extract::<Option<Header<Authorization>>>(&mut req), Ok(None));
+}
+
+#[test]
+fn test_optional_extractors() {
+    // Missing values are `None`
+    let mut req = HttpRequest::default();
+    assert_eq!(extract::<Option<Json<Raw>>>(&mut req), Ok(None));
+    assert_eq!(extract::<Option<Form<Pagination>>>(&mut req), Ok(None));
+    assert_eq!(extract::<Option<Query<Pagination>>>(&mut req), Ok(None));
+    assert_eq!(extract::<Option<Extension<&str>>>(&mut req), Ok(None));
+
+    // Malformed ones still fail the request
+    let mut req = request(&[("Content-Type", "application/json")], "[]");
+    req.query.insert("page".to_owned(), "x".to_owned());
+    assert_eq!(
+        extract::<Option<Json<Raw>>>(&mut req),
+        Err(StatusCode::BadRequest)
+    );
+    assert_eq!(
+        extract::<Option<Query<Pagination>>>(&mut req),
+        Err(StatusCode::BadRequest)
+    );
+    assert_eq!(
+        extract::<Option<Form<Pagination>>>(&mut req),
+        Err(StatusCode::UnsupportedMediaType)
+    );
+
+    let mut req = request(&[("Content-Type", "application/json")], "{}");
+    req.extensions_mut().insert("user");
+    assert_eq!(
+        extract::<Option<Json<Raw>>>(&mut req),
+        Ok(Some(Json(Raw("{}".to_owned()))))
+    );
+    assert_eq!(
+        extract::<Option<Extension<&str>>>(&mut req),
+        Ok(Some(Extension("user")))
+    );
+}
+
+#[test]
+fn test_extension_extractor() {
+    let mut req = HttpRequest::default();
+    assert_eq!(
+        extract::<Extension<&str>>(&mut req),
+        Err(StatusCode::InternalServerError)
+    );
+
+    req.extensions_mut().insert("user");
+    assert_eq!(extract::<Extension<&str>>(&mut req), Ok(Extension("user")));
+}
+
+fn ok(text: String) -> HttpResult {
+    Ok(HttpResponse::builder().text(text).build())
+}
+
+fn body(result: HttpResult) -> String {
+    String::from_utf8(result.unwrap().body.unwrap()).unwrap()
+}
+
+#[test]
+fn test_handler_arities() {
+    fn classic(state: Arc<u32>, req: HttpRequest) -> HttpResult {
+        ok(format!("{state} {}", req.route))
+    }
+
+    fn extractors(State(state): State<u32>, Path(id): Path<u64>, Bytes(body): Bytes) -> HttpResult {
+        ok(format!("{state} {id} {}", body.len()))
+    }
+
+    let mut req = request(&[], "abc");
+    req.route = "/x".to_owned();
+    req.path_params.insert("id".to_owned(), "3".to_owned());
+
+    assert_eq!(body(classic.handle(Arc::new(7), req.clone())), "7 /x");
+    assert_eq!(body(extractors.handle(Arc::new(7), req.clone())), "7 3 3");
+    assert_eq!(
+        body((|| ok("none".to_owned())).handle(Arc::new(7), req)),
+        "none"
+    );
+
+    let failed = extractors
+        .handle(Arc::new(7), HttpRequest::default())
+        .unwrap_err();
+    assert_eq!(
+        failed.downcast_ref::<HttpError>().map(|e| e.status),
+        Some(StatusCode::InternalServerError)
+    );
+}
+
+#[derive(Debug)]
+enum UserError {
+    NotFound,
+}
+
+impl IntoResponse for UserError {
+    fn into_response(self) -> HttpResponse {
+        match self {
+            UserError::NotFound => (StatusCode::NotFound, "no such user").into_response(),
+        }
+    }
+}
+
+struct Greeting(&'static str);
+
+impl ToJson for Greeting {
+    fn to_json(&self) -> String {
+        format!("{{\"greeting\":\"{}\"}}", self.0)
+    }
+}
+
+#[test]
+fn test_handler_return_types() {
+    fn user(Path(id): Path<u32>) -> Result<String, UserError> {
+        match id {
+            1 => Ok("alice".to_owned()),
+            _ => Err(UserError::NotFound),
+        }
+    }
+
+    fn failing() -> Result<&'static str, Box<dyn Error>> {
+        Err("broken".into())
+    }
+
+    let state = Arc::new(7);
+    let mut req = HttpRequest::default();
+    req.path_params.insert("id".to_owned(), "1".to_owned());
+    assert_eq!(body(user.handle(state.clone(), req.clone())), "alice");
+
+    req.path_params.insert("id".to_owned(), "2".to_owned());
+    let response = user.handle(state.clone(), req.clone()).unwrap();
+    assert_eq!(response.status, StatusCode::NotFound);
+
+    let response = (|| Json(Greeting("hi")))
+        .handle(state.clone(), req.clone())
+        .unwrap();
+    assert_eq!(response.headers.content_type(), Some("application/json"));
+    assert_eq!(
+        response.body.as_deref(),
+        Some(br#"{"greeting":"hi"}"#.as_ref())
+    );
+
+    assert!(failing.handle(state, req).is_err());
+}
@@ -0,0 +1,236 @@
+use super::{
+    consts::headers::{
+        ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LEN, CONTENT_RANGE, CONTENT_TYPE,
+        ETAG, VARY,
+    },
+    BodyStream, ETag, HttpResponse, StatusCode,
+};
+use crate::compress::{compress, Encoder, Format};
+use std::io::{self, Cursor, Read, Write};
+
+/// Smaller bodies aren't worth the overhead of compressing them
+const MIN_SIZE: u64 = 1024;
+
+/// The content codings responses can be compressed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Gzip,
+    /// zlib framed DEFLATE, despite the name
+    Deflate,
+}
+
+impl ContentCoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+        }
+    }
+
+    fn format(&self) -> Format {
+        match self {
+            ContentCoding::Gzip => Format::Gzip,
+            ContentCoding::Deflate => Format::Zlib,
+        }
+    }
+}
+
+/// Picks the coding with the highest q-value in an `Accept-Encoding` header, gzip on a tie.
+///
+/// `*` stands for every coding not listed explicitly, `q=0` rules a coding out.
+/// `None` if neither gzip nor deflate are acceptable.
+pub fn negotiate_encoding(accept_encoding: &str) -> Option<ContentCoding> {
+    let (mut gzip, mut deflate, mut any) = (None, None, None);
+
+    for entry in accept_encoding.split(',') {
+        let mut params = entry.split(';');
+        let coding = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let mut q = Some(1.0);
+        for param in params {
+            if let Some((key, value)) = param.split_once('=') {
+                if key.trim().eq_ignore_ascii_case("q") {
+                    q = value
+                        .trim()
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|q| (0.0..=1.0).contains(q));
+                }
+            }
+        }
+        // Entries with an invalid weight are ignored
+        let Some(q) = q else {
+            continue;
+        };
+
+        match coding.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(q),
+            "deflate" => deflate = Some(q),
+            "*" => any = Some(q),
+            _ => {}
+        }
+    }
+
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    match (gzip, deflate) {
+        (gzip, deflate) if gzip > 0.0 && gzip >= deflate => Some(ContentCoding::Gzip),
+        (_, deflate) if deflate > 0.0 => Some(ContentCoding::Deflate),
+        _ => None,
+    }
+}
+
+/// Whether compressing a body of this type makes sense, media and archives are compressed already
+fn compressible(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return true;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match mime.as_str() {
+        "image/svg+xml" => true,
+        "application/zip"
+        | "application/gzip"
+        | "application/x-gzip"
+        | "application/zstd"
+        | "application/x-bzip2"
+        | "application/x-xz"
+        | "application/x-7z-compressed"
+        | "application/x-rar-compressed"
+        | "application/pdf"
+        | "font/woff"
+        | "font/woff2"
+        | "multipart/byteranges" => false,
+        mime => !["image/", "video/", "audio/"]
+            .iter()
+            .any(|prefix| mime.starts_with(prefix)),
+    }
+}
+
+impl HttpResponse {
+    /// Compresses the body with the best coding `accept_encoding` allows.
+    ///
+    /// Bodies that are small, already compressed or of a compressed media type are left as is.
+    /// Streamed bodies are compressed while they are sent, with chunked transfer encoding.
+    /// A strong `ETag` is made weak, as the compressed bytes differ from the original ones.
+    pub fn compress(mut self, accept_encoding: Option<&str>) -> Self {
+        let status = self.status;
+        if status == StatusCode::NotModified {
+            // Stands in for the response that would have been compressed, so it varies the same way
+            self.vary_accept_encoding();
+            return self;
+        }
+        if matches!(status, StatusCode::NoContent | StatusCode::PartialContent)
+            || status.code() < 200
+        {
+            return self;
+        }
+
+        let headers = &self.headers.values;
+        if headers.contains_key(CONTENT_ENCODING)
+            || headers.contains_key(CONTENT_RANGE)
+            || !compressible(headers.get(CONTENT_TYPE).map(String::as_str))
+        {
+            return self;
+        }
+
+        let len = match (&self.body, &self.stream) {
+            (Some(body), _) => Some(body.len() as u64),
+            (None, Some(stream)) => stream.content_length(),
+            (None, None) => return self,
+        };
+        if len.is_some_and(|len| len < MIN_SIZE) {
+            return self;
+        }
+
+        // Caches have to know the response depends on the header, even if it wasn't compressed
+        self.vary_accept_encoding();
+
+        let Some(coding) = accept_encoding.and_then(negotiate_encoding) else {
+            return self;
+        };
+
+        if let Some(body) = &self.body {
+            let compressed = compress(body, coding.format());
+            if compressed.len() >= body.len() {
+                return self;
+            }
+            self.body = Some(compressed);
+        } else if let Some(stream) = self.stream.take() {
+            let reader = CompressReader {
+                source: stream,
+                encoder: Some(Encoder::new(Vec::new(), coding.format())),
+                output: Cursor::default(),
+            };
+            self.stream = Some(BodyStream::new(reader, None));
+        }
+
+        let headers = &mut self.headers.values;
+        headers.insert(CONTENT_ENCODING.to_owned(), coding.as_str().to_owned());
+        headers.remove(CONTENT_LEN);
+        // Ranges would refer to the compressed bytes, which aren't stable
+        headers.remove(ACCEPT_RANGES);
+        if let Some(etag) = headers.get(ETAG).and_then(|etag| ETag::parse(etag)) {
+            headers.insert(ETAG.to_owned(), etag.into_weak().to_string());
+        }
+
+        self
+    }
+
+    fn vary_accept_encoding(&mut self) {
+        let vary = match self.headers.values.remove(VARY) {
+            Some(vary)
+                if vary
+                    .split(',')
+                    .any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case(ACCEPT_ENCODING)) =>
+            {
+                vary
+            }
+            Some(vary) => format!("{vary}, Accept-Encoding"),
+            None => "Accept-Encoding".to_owned(),
+        };
+        self.headers.values.insert(VARY.to_owned(), vary);
+    }
+}
+
+/// Compresses a stream as it is read, flushing after every read from the source
+/// so data is sent as soon as it is available
+struct CompressReader<R> {
+    source: R,
+    /// `None` once the source is exhausted and the stream is finished
+    encoder: Option<Encoder<Vec<u8>>>,
+    output: Cursor<Vec<u8>>,
+}
+
+impl<R: Read> Read for CompressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.output.read(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            let Some(encoder) = self.encoder.as_mut() else {
+                return Ok(0);
+            };
+
+            let mut chunk = [0; 16 * 1024];
+            let read = self.source.read(&mut chunk)?;
+            let output = if read == 0 {
+                self.encoder.take().unwrap().finish()?
+            } else {
+                encoder.write_all(&chunk[..read])?;
+                encoder.flush()?;
+                std::mem::take(encoder.get_mut())
+            };
+            self.output = Cursor::new(output);
+        }
+    }
+}
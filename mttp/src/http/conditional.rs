@@ -0,0 +1,287 @@
+use super::{
+    consts::headers::{
+        CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE,
+        LAST_MODIFIED,
+    },
+    format_http_date, parse_http_date, HttpError, HttpRequest, HttpResponse, HttpResponseBuilder,
+    Method, StatusCode,
+};
+use std::{fmt::Display, time::SystemTime};
+
+/// Headers a 304 response keeps, everything else describes the body it doesn't have
+const NOT_MODIFIED_HEADERS: [&str; 7] = [
+    "Cache-Control",
+    "Content-Location",
+    "Date",
+    ETAG,
+    "Expires",
+    LAST_MODIFIED,
+    "Vary",
+];
+
+/// An entity tag identifying one version of a response body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    /// A weak tag only promises an equivalent body, not an identical one
+    pub weak: bool,
+    /// The opaque tag without quotes
+    pub tag: String,
+}
+
+impl ETag {
+    pub fn strong(tag: &str) -> Self {
+        Self {
+            weak: false,
+            tag: tag.to_owned(),
+        }
+    }
+
+    pub fn weak(tag: &str) -> Self {
+        Self {
+            weak: true,
+            tag: tag.to_owned(),
+        }
+    }
+
+    /// A strong tag computed from a hash of `body`
+    pub fn from_body(body: &[u8]) -> Self {
+        // FNV-1a, stable across runs and platforms unlike the std hashers
+        let hash = body.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        Self::strong(&format!("{hash:016x}-{:x}", body.len()))
+    }
+
+    /// The same tag, but weak
+    pub fn into_weak(self) -> Self {
+        Self { weak: true, ..self }
+    }
+
+    /// Parses a single tag like `"abc"` or `W/"abc"`
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+
+        // etagc is any visible ASCII character except the quote
+        if !tag.bytes().all(|b| b == 0x21 || (0x23..=0x7e).contains(&b)) {
+            return None;
+        }
+
+        Some(Self {
+            weak,
+            tag: tag.to_owned(),
+        })
+    }
+
+    /// Both tags are strong and equal, used by `If-Match` and `If-Range`
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags are equal ignoring weakness, used by `If-None-Match`
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl Display for ETag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// The conditional headers of a request, evaluated against a response with
+/// [`Preconditions::evaluate`] or [`HttpResponse::conditional`].
+///
+/// Kept separately from the request so middlewares can check them after the request
+/// has been handed to the handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preconditions {
+    method: Method,
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+    if_unmodified_since: Option<String>,
+}
+
+impl Preconditions {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let header = |key| req.headers.get(key).cloned();
+
+        Self {
+            method: req.method,
+            if_match: header(IF_MATCH),
+            if_none_match: header(IF_NONE_MATCH),
+            if_modified_since: header(IF_MODIFIED_SINCE),
+            if_unmodified_since: header(IF_UNMODIFIED_SINCE),
+        }
+    }
+
+    /// Evaluates the preconditions in the order of RFC 9110 section 13.2.2 against the current
+    /// version of the resource.
+    ///
+    /// Returns the status to answer with instead, 304 Not Modified or 412 Precondition Failed,
+    /// or `None` if the request should be answered normally.
+    ///
+    /// Without either validator the resource is taken to not exist yet, so `If-Match: *` fails
+    /// and `If-None-Match: *` passes, as a create-if-absent `PUT` expects.
+    pub fn evaluate(
+        &self,
+        etag: Option<&ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Option<StatusCode> {
+        let exists = etag.is_some() || last_modified.is_some();
+        self.evaluate_for(exists, etag, last_modified)
+    }
+
+    /// [`Preconditions::evaluate`] with `exists` telling whether there is a current
+    /// representation, which is what `*` matches
+    fn evaluate_for(
+        &self,
+        exists: bool,
+        etag: Option<&ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Option<StatusCode> {
+        let date = |header: &Option<String>| header.as_deref().and_then(parse_http_date);
+
+        if let Some(if_match) = &self.if_match {
+            if !matches(if_match, exists, etag, ETag::strong_eq) {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        } else if let Some(since) = date(&self.if_unmodified_since) {
+            // Without a modification date the condition can't be true
+            if last_modified.is_none_or(|modified| newer(modified, since)) {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        }
+
+        if let Some(if_none_match) = &self.if_none_match {
+            if matches(if_none_match, exists, etag, ETag::weak_eq) {
+                return Some(match self.method {
+                    Method::Get => StatusCode::NotModified,
+                    _ => StatusCode::PreconditionFailed,
+                });
+            }
+        } else if let (Method::Get, Some(since), Some(modified)) =
+            (self.method, date(&self.if_modified_since), last_modified)
+        {
+            if !newer(modified, since) {
+                return Some(StatusCode::NotModified);
+            }
+        }
+
+        None
+    }
+
+    /// Replaces a successful `response` with a 304 or 412 if the preconditions require it.
+    ///
+    /// The resource's version is taken from the `ETag` and `Last-Modified` headers of `response`.
+    /// Other responses are returned unchanged, as their status takes precedence.
+    pub fn apply(&self, mut response: HttpResponse) -> HttpResponse {
+        if !(200..300).contains(&response.status.code()) {
+            return response;
+        }
+
+        let etag = response
+            .headers
+            .get(ETAG)
+            .and_then(|etag| ETag::parse(etag));
+        let last_modified = response
+            .headers
+            .get(LAST_MODIFIED)
+            .and_then(|modified| parse_http_date(modified));
+
+        // The response is successful, so there is a current representation
+        let Some(status) = self.evaluate_for(true, etag.as_ref(), last_modified) else {
+            return response;
+        };
+
+        let headers = &mut response.headers.values;
+        match status {
+            StatusCode::NotModified => {
+                headers.retain(|key, _| NOT_MODIFIED_HEADERS.contains(&key.as_str()));
+            }
+            _ => {
+                headers.remove(CONTENT_TYPE);
+            }
+        }
+
+        response.status = status;
+        response.body = None;
+        response.stream = None;
+        response
+    }
+}
+
+/// Whether one of the comma separated tags in `header` matches `etag`,
+/// or it is a `*` and the resource `exists`
+fn matches(header: &str, exists: bool, etag: Option<&ETag>, eq: fn(&ETag, &ETag) -> bool) -> bool {
+    if header.trim() == "*" {
+        return exists;
+    }
+    let Some(etag) = etag else {
+        return false;
+    };
+
+    header
+        .split(',')
+        .filter_map(ETag::parse)
+        .any(|tag| eq(&tag, etag))
+}
+
+/// HTTP dates only have a precision of seconds
+fn newer(modified: SystemTime, since: SystemTime) -> bool {
+    modified
+        .duration_since(since)
+        .is_ok_and(|newer| newer.as_secs() > 0)
+}
+
+impl HttpRequest {
+    /// Checks the conditional headers against the current version of the resource,
+    /// see [`Preconditions::evaluate`].
+    ///
+    /// Meant for handlers of requests that modify state: call it before changing anything, so
+    /// e.g. a `PUT` with a stale `If-Match` fails with 412 Precondition Failed and leaves the
+    /// resource as it was. [`conditional_requests`](crate::server::conditional_requests) can't
+    /// do that, it only sees the response after the handler ran. Pass `None` for both if the
+    /// resource doesn't exist yet.
+    ///
+    /// A 304 can't be an error, it has no body. So a GET that would be answered with one passes,
+    /// [`conditional_requests`](crate::server::conditional_requests) or
+    /// [`HttpResponse::conditional`] turn the response into the 304.
+    pub fn check_preconditions(
+        &self,
+        etag: Option<&ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Result<(), HttpError> {
+        match Preconditions::from_request(self).evaluate(etag, last_modified) {
+            Some(StatusCode::NotModified) | None => Ok(()),
+            Some(status) => Err(HttpError::new(status, "Precondition failed")),
+        }
+    }
+}
+
+impl HttpResponse {
+    /// Answers the conditional headers of `req` for this response,
+    /// see [`Preconditions::apply`]
+    pub fn conditional(self, req: &HttpRequest) -> Self {
+        Preconditions::from_request(req).apply(self)
+    }
+}
+
+impl HttpResponseBuilder {
+    pub fn etag(self, etag: &ETag) -> Self {
+        self.header(ETAG, etag.to_string())
+    }
+
+    pub fn last_modified(self, time: SystemTime) -> Self {
+        self.header(LAST_MODIFIED, format_http_date(time))
+    }
+}
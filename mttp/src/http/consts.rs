@@ -4,7 +4,26 @@ pub const HTTP_VER_STR: &str = "HTTP/1.1";
 
 pub mod headers {
     pub const CONTENT_LEN: &str = "Content-Length";
+    pub const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+    pub const ACCEPT_ENCODING: &str = "Accept-Encoding";
+    pub const CONTENT_ENCODING: &str = "Content-Encoding";
+    pub const VARY: &str = "Vary";
     pub const COOKIES: &str = "Cookie";
     pub const CONTENT_TYPE: &str = "Content-Type";
+    #[allow(dead_code)]
     pub const ORIGIN: &str = "Origin";
+    pub const FORWARDED: &str = "Forwarded";
+    pub const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+    pub const USER_AGENT: &str = "User-Agent";
+    pub const AUTHORIZATION: &str = "Authorization";
+    pub const ETAG: &str = "ETag";
+    pub const LAST_MODIFIED: &str = "Last-Modified";
+    pub const IF_MATCH: &str = "If-Match";
+    pub const IF_NONE_MATCH: &str = "If-None-Match";
+    pub const IF_MODIFIED_SINCE: &str = "If-Modified-Since";
+    pub const IF_UNMODIFIED_SINCE: &str = "If-Unmodified-Since";
+    pub const RANGE: &str = "Range";
+    pub const IF_RANGE: &str = "If-Range";
+    pub const ACCEPT_RANGES: &str = "Accept-Ranges";
+    pub const CONTENT_RANGE: &str = "Content-Range";
 }
@@ -1,4 +1,5 @@
-use super::HttpResponse;
+use super::{HttpResponse, IntoResponse, StatusCode};
+use crate::url::PathError;
 use std::{fmt::Display, io};
 
 #[derive(Debug)]
@@ -7,6 +8,7 @@ pub enum Error {
     Empty,
     InvalidHeader,
     NoUri,
+    InvalidPath(PathError),
     InvalidMethod { recieved: String },
     InvalidHeaderValue { header: String },
     BodyTooShort { expt: usize, got: usize },
@@ -21,6 +23,7 @@ impl Display for Error {
             Error::Empty => write!(f, "A required field was empty"),
             Error::InvalidHeader => write!(f, "A header was invalid"),
             Error::NoUri => write!(f, "No URI was provided"),
+            Error::InvalidPath(e) => write!(f, "Invalid path: {e}"),
             Error::InvalidMethod { recieved } => write!(f, "Invalid method '{recieved}'"),
             Error::InvalidHeaderValue { header } => {
                 write!(f, "Header {header} contains an invalid value")
@@ -50,3 +53,55 @@ impl From<io::Error> for Error {
         Error::Io(value)
     }
 }
+
+/// An error that should reach the client with a specific status code.
+///
+/// When a handler returns it, the default error handler responds with its status and message
+/// instead of a generic 500.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BadRequest, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NotFound, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::InternalServerError, message)
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::builder()
+            .status(self.status)
+            .text(self.message.clone())
+            .build()
+    }
+}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> HttpResponse {
+        self.response()
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
@@ -0,0 +1,63 @@
+use super::{HttpError, HttpRequest, StatusCode};
+use crate::url::{QueryOptions, QueryParams};
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormError {
+    /// The body is not `application/x-www-form-urlencoded`
+    UnsupportedContentType(Option<String>),
+    InvalidUtf8,
+    /// A field with a broken percent encoding or that doesn't decode to UTF-8
+    InvalidField(String),
+}
+
+impl HttpRequest {
+    /// Decodes an `application/x-www-form-urlencoded` body, a missing body is an empty form.
+    ///
+    /// `+` is decoded as a space and repeated keys are kept in order.
+    pub fn form(&self) -> Result<QueryParams, FormError> {
+        if self
+            .expect_content_type("application/x-www-form-urlencoded")
+            .is_err()
+        {
+            return Err(FormError::UnsupportedContentType(
+                self.headers.content_type().map(str::to_owned),
+            ));
+        }
+
+        let body = std::str::from_utf8(self.body.as_deref().unwrap_or_default())
+            .map_err(|_| FormError::InvalidUtf8)?;
+
+        QueryParams::try_parse_with(body, QueryOptions::default())
+            .map_err(|field| FormError::InvalidField(field.to_owned()))
+    }
+}
+
+impl Display for FormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormError::UnsupportedContentType(Some(content_type)) => {
+                write!(f, "Expected a form body, got {content_type}")
+            }
+            FormError::UnsupportedContentType(None) => {
+                write!(f, "Expected a form body, got no content type")
+            }
+            FormError::InvalidUtf8 => write!(f, "Form body is not valid UTF-8"),
+            FormError::InvalidField(field) => write!(f, "Invalid form field '{field}'"),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+/// Lets handlers use `?` on [`HttpRequest::form`] to respond with a 415 or 400
+impl From<FormError> for HttpError {
+    fn from(value: FormError) -> Self {
+        let status = match value {
+            FormError::UnsupportedContentType(_) => StatusCode::UnsupportedMediaType,
+            FormError::InvalidUtf8 | FormError::InvalidField(_) => StatusCode::BadRequest,
+        };
+
+        HttpError::new(status, value.to_string())
+    }
+}
@@ -0,0 +1,93 @@
+use super::{
+    consts::headers::{FORWARDED, X_FORWARDED_FOR},
+    HeaderMap,
+};
+use crate::transport::Address;
+use std::net::{IpAddr, SocketAddr};
+
+/// Resolves the IP of the client that originally sent a request.
+///
+/// If the request came from a trusted proxy, the proxy chain from the `Forwarded` header
+/// (or `X-Forwarded-For` if there is none) is walked from the nearest hop outwards,
+/// and the first address that isn't a trusted proxy itself is the client.
+/// Unix socket peers are treated as trusted proxies if `trust_unix` is set.
+pub(crate) fn client_ip(
+    peer: Address,
+    headers: &HeaderMap,
+    trusted: &[IpAddr],
+    trust_unix: bool,
+) -> Option<IpAddr> {
+    let peer_ip = match peer {
+        Address::Tcp(addr) => Some(addr.ip()),
+        Address::Unix | Address::Unknown => None,
+    };
+
+    let peer_is_trusted = match peer {
+        Address::Tcp(addr) => trusted.contains(&addr.ip()),
+        Address::Unix => trust_unix,
+        Address::Unknown => false,
+    };
+
+    if !peer_is_trusted {
+        return peer_ip;
+    }
+
+    let chain = if let Some(forwarded) = headers.get(FORWARDED) {
+        forwarded_for(forwarded)
+    } else if let Some(forwarded_for) = headers.get(X_FORWARDED_FOR) {
+        forwarded_for.split(',').map(parse_node).collect()
+    } else {
+        return peer_ip;
+    };
+
+    let mut client = peer_ip;
+    for hop in chain.into_iter().rev() {
+        // An unparsable or obfuscated hop can't be trusted to have reported the next one honestly
+        let Some(hop) = hop else {
+            break;
+        };
+
+        client = Some(hop);
+        if !trusted.contains(&hop) {
+            break;
+        }
+    }
+
+    client
+}
+
+/// Extracts the `for=` parameter of every element in a `Forwarded` header (RFC 7239).
+///
+/// Elements without one still count as a hop, just an unknown one.
+fn forwarded_for(header: &str) -> Vec<Option<IpAddr>> {
+    header
+        .split(',')
+        .map(|element| {
+            element
+                .split(';')
+                .find_map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    key.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value))
+                })
+                .flatten()
+        })
+        .collect()
+}
+
+/// Parses a single node like `192.0.2.60`, `"192.0.2.60:443"` or `"[2001:db8::17]:4711"`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    // IPv6 in brackets without a port
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
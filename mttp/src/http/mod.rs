@@ -1,16 +1,33 @@
+pub(crate) mod compression;
+pub(crate) mod conditional;
 pub(crate) mod consts;
+pub(crate) mod date;
 pub mod error;
+pub(crate) mod extensions;
+pub(crate) mod form;
+pub(crate) mod forwarded;
 pub(crate) mod header;
+pub(crate) mod multipart;
 pub(crate) mod protocol;
+pub(crate) mod range;
 pub(crate) mod request;
 pub(crate) mod response;
 pub(crate) mod status;
 
-pub use error::Error;
+pub use compression::*;
+pub use conditional::*;
+pub use date::*;
+pub use error::{Error, HttpError};
+pub use extensions::*;
+pub use form::*;
 pub use header::*;
+pub use multipart::*;
+pub use range::*;
 pub use request::*;
 pub use response::*;
 pub use status::*;
 
+pub use crate::url::{QueryOptions, QueryParams};
+
 #[cfg(test)]
 mod test;
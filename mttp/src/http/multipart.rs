@@ -0,0 +1,652 @@
+use super::{BodyStream, HeaderMap, HttpError, HttpRequest, StatusCode};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Bounds on what the multipart parser accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultipartLimits {
+    pub max_parts: usize,
+    /// The maximum size of the header block of a single part
+    pub max_header_size: usize,
+    /// The maximum size of a text field, text fields are always kept in memory
+    pub max_field_size: usize,
+    /// The maximum size of a file part
+    pub max_file_size: usize,
+    /// File parts larger than this are spilled to a temporary file
+    pub memory_threshold: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_parts: 100,
+            max_header_size: 8 * 1024,
+            max_field_size: 64 * 1024,
+            max_file_size: 16 * 1024 * 1024,
+            memory_threshold: 256 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MultipartError {
+    /// The body is not `multipart/form-data`
+    UnsupportedContentType(Option<String>),
+    /// The `boundary` parameter is missing, empty or longer than 70 characters
+    InvalidBoundary,
+    /// A part has malformed headers or no `Content-Disposition` with a name
+    InvalidPartHeaders,
+    /// The body ended before the closing boundary
+    UnexpectedEnd,
+    TooManyParts {
+        max: usize,
+    },
+    HeadersTooLarge {
+        max: usize,
+    },
+    PartTooLarge {
+        name: String,
+        max: usize,
+    },
+    Io(io::Error),
+}
+
+/// A streaming `multipart/form-data` parser.
+///
+/// Parts are read one at a time from `reader`, so only the part currently being parsed is held,
+/// and file parts above [`MultipartLimits::memory_threshold`] go to a temporary file instead.
+pub struct Multipart<R> {
+    reader: R,
+    buf: Vec<u8>,
+    /// `\r\n--` followed by the boundary
+    delimiter: Vec<u8>,
+    limits: MultipartLimits,
+    temp_dir: Option<PathBuf>,
+    parts: usize,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Parts,
+    Done,
+}
+
+/// A single field or file of a multipart body
+#[derive(Debug)]
+pub struct Part {
+    pub name: String,
+    /// The file name as sent by the client, don't use it as a path without sanitizing it
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: HeaderMap,
+    pub data: PartData,
+}
+
+#[derive(Debug)]
+pub enum PartData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+/// A file that is deleted when dropped, unless it is [persisted](TempFile::persist)
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    len: u64,
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str) -> Result<Self, MultipartError> {
+        Self::with_limits(reader, boundary, MultipartLimits::default())
+    }
+
+    pub fn with_limits(
+        reader: R,
+        boundary: &str,
+        limits: MultipartLimits,
+    ) -> Result<Self, MultipartError> {
+        if boundary.is_empty() || boundary.len() > 70 {
+            return Err(MultipartError::InvalidBoundary);
+        }
+
+        Ok(Self {
+            reader,
+            // The first boundary may directly start the body, so the stream is treated as if it
+            // started with a line break. That way every boundary can be found by the same delimiter.
+            buf: b"\r\n".to_vec(),
+            delimiter: [b"\r\n--", boundary.as_bytes()].concat(),
+            limits,
+            temp_dir: None,
+            parts: 0,
+            state: State::Preamble,
+        })
+    }
+
+    /// Where spilled file parts are written, defaults to [`std::env::temp_dir`]
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(dir.into());
+        self
+    }
+
+    /// Reads the next part, `None` after the closing boundary.
+    ///
+    /// After an error the parser is done and only returns `None`.
+    pub fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        let part = self.read_part();
+        if !matches!(part, Ok(Some(_))) {
+            self.state = State::Done;
+        }
+        part
+    }
+
+    fn read_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        match self.state {
+            State::Done => return Ok(None),
+            State::Preamble => {
+                self.skip_preamble()?;
+                self.state = State::Parts;
+            }
+            State::Parts => {}
+        }
+
+        if self.after_delimiter()? {
+            return Ok(None);
+        }
+
+        if self.parts == self.limits.max_parts {
+            return Err(MultipartError::TooManyParts {
+                max: self.limits.max_parts,
+            });
+        }
+        self.parts += 1;
+
+        let headers = self.read_headers()?;
+        let (name, filename) = headers
+            .values
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Content-Disposition"))
+            .and_then(|(_, value)| content_disposition(value))
+            .ok_or(MultipartError::InvalidPartHeaders)?;
+        let content_type = headers
+            .values
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value.clone());
+
+        let data = self.read_body(&name, filename.is_some())?;
+
+        Ok(Some(Part {
+            name,
+            filename,
+            content_type,
+            headers,
+            data,
+        }))
+    }
+
+    /// Reads more of the body into the buffer, `false` at the end of the stream
+    fn fill(&mut self) -> Result<bool, MultipartError> {
+        let len = self.buf.len();
+        self.buf.resize(len + READ_CHUNK, 0);
+
+        let read = loop {
+            match self.reader.read(&mut self.buf[len..]) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buf.truncate(len);
+                    return Err(MultipartError::Io(e));
+                }
+            }
+        };
+
+        self.buf.truncate(len + read);
+        Ok(read > 0)
+    }
+
+    /// Makes sure at least `n` bytes are buffered
+    fn require(&mut self, n: usize) -> Result<(), MultipartError> {
+        while self.buf.len() < n {
+            if !self.fill()? {
+                return Err(MultipartError::UnexpectedEnd);
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_preamble(&mut self) -> Result<(), MultipartError> {
+        loop {
+            if let Some(i) = find(&self.buf, &self.delimiter) {
+                self.buf.drain(..i + self.delimiter.len());
+                return Ok(());
+            }
+
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                self.buf.drain(..self.buf.len() - keep);
+            }
+            if !self.fill()? {
+                return Err(MultipartError::UnexpectedEnd);
+            }
+        }
+    }
+
+    /// Consumes what follows a boundary, returns `true` if it was the closing one
+    fn after_delimiter(&mut self) -> Result<bool, MultipartError> {
+        self.require(2)?;
+        if self.buf.starts_with(b"--") {
+            // Everything after the closing boundary is an epilogue that is ignored
+            return Ok(true);
+        }
+
+        // Transport padding between the boundary and the line break
+        loop {
+            self.require(1)?;
+            match self.buf[0] {
+                b' ' | b'\t' => {
+                    self.buf.remove(0);
+                }
+                _ => break,
+            }
+        }
+
+        self.require(2)?;
+        if !self.buf.starts_with(b"\r\n") {
+            return Err(MultipartError::InvalidPartHeaders);
+        }
+        self.buf.drain(..2);
+        Ok(false)
+    }
+
+    fn read_headers(&mut self) -> Result<HeaderMap, MultipartError> {
+        self.require(2)?;
+        if self.buf.starts_with(b"\r\n") {
+            self.buf.drain(..2);
+            return Ok(HeaderMap::empty());
+        }
+
+        let end = loop {
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buf.len() > self.limits.max_header_size {
+                break self.buf.len();
+            }
+            if !self.fill()? {
+                return Err(MultipartError::UnexpectedEnd);
+            }
+        };
+
+        if end > self.limits.max_header_size {
+            return Err(MultipartError::HeadersTooLarge {
+                max: self.limits.max_header_size,
+            });
+        }
+
+        let block = std::str::from_utf8(&self.buf[..end])
+            .map_err(|_| MultipartError::InvalidPartHeaders)?;
+        let values = block
+            .split("\r\n")
+            .map(|line| {
+                let (key, value) = line.split_once(':')?;
+                Some((key.trim().to_owned(), value.trim().to_owned()))
+            })
+            .collect::<Option<HashMap<_, _>>>()
+            .ok_or(MultipartError::InvalidPartHeaders)?;
+
+        self.buf.drain(..end + 4);
+        Ok(HeaderMap { values })
+    }
+
+    fn read_body(&mut self, name: &str, is_file: bool) -> Result<PartData, MultipartError> {
+        let max = if is_file {
+            self.limits.max_file_size
+        } else {
+            self.limits.max_field_size
+        };
+        let mut sink = Sink {
+            data: PartData::Memory(Vec::new()),
+            file: None,
+            len: 0,
+        };
+
+        loop {
+            let (len, consumed) = match find(&self.buf, &self.delimiter) {
+                Some(i) => (i, i + self.delimiter.len()),
+                // The end of the buffer may be the start of a delimiter, so it stays for the next round
+                None => {
+                    let len = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+                    (len, len)
+                }
+            };
+
+            if sink.len + len > max {
+                return Err(MultipartError::PartTooLarge {
+                    name: name.to_owned(),
+                    max,
+                });
+            }
+
+            sink.len += len;
+            if let PartData::Memory(data) = &mut sink.data {
+                data.extend_from_slice(&self.buf[..len]);
+                if is_file && data.len() > self.limits.memory_threshold {
+                    sink.spill(self.temp_dir.as_deref())?;
+                }
+            } else if let Some(file) = &mut sink.file {
+                file.write_all(&self.buf[..len])
+                    .map_err(MultipartError::Io)?;
+            }
+
+            let found = consumed != len;
+            self.buf.drain(..consumed);
+            if found {
+                return sink.finish();
+            }
+
+            if !self.fill()? {
+                return Err(MultipartError::UnexpectedEnd);
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Multipart<R> {
+    type Item = Result<Part, MultipartError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_part().transpose()
+    }
+}
+
+/// Where the body of the current part goes, first in memory and then to a file
+struct Sink {
+    data: PartData,
+    file: Option<BufWriter<File>>,
+    len: usize,
+}
+
+impl Sink {
+    fn spill(&mut self, dir: Option<&Path>) -> Result<(), MultipartError> {
+        let (temp, file) = TempFile::create(dir).map_err(MultipartError::Io)?;
+        let mut file = BufWriter::new(file);
+
+        if let PartData::Memory(data) = &self.data {
+            file.write_all(data).map_err(MultipartError::Io)?;
+        }
+
+        self.data = PartData::File(temp);
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<PartData, MultipartError> {
+        if let Some(file) = self.file.take() {
+            file.into_inner()
+                .map_err(|e| MultipartError::Io(e.into_error()))?
+                .sync_all()
+                .map_err(MultipartError::Io)?;
+        }
+        if let PartData::File(temp) = &mut self.data {
+            temp.len = self.len as u64;
+        }
+        Ok(self.data)
+    }
+}
+
+impl Part {
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.data {
+            PartData::Memory(data) => data.len() as u64,
+            PartData::File(file) => file.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The value of a field, `None` if it isn't UTF-8 or was spilled to a file
+    pub fn text(&self) -> Option<&str> {
+        match &self.data {
+            PartData::Memory(data) => std::str::from_utf8(data).ok(),
+            PartData::File(_) => None,
+        }
+    }
+
+    /// The whole content, read back from the file if it was spilled
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(data) => Ok(data.clone()),
+            PartData::File(file) => fs::read(file.path()),
+        }
+    }
+}
+
+impl TempFile {
+    fn create(dir: Option<&Path>) -> io::Result<(Self, File)> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = dir.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        loop {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.subsec_nanos());
+            let path = dir.join(format!(
+                "mttp-upload-{}-{}-{nanos}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            match options.open(&path) {
+                Ok(file) => return Ok((Self { path, len: 0 }, file)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    /// Moves the file to `to` so it is kept, copying it if `to` is on another file system
+    pub fn persist(mut self, to: impl AsRef<Path>) -> io::Result<()> {
+        if fs::rename(&self.path, &to).is_err() {
+            fs::copy(&self.path, &to)?;
+            fs::remove_file(&self.path)?;
+        }
+        self.path = PathBuf::new();
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl HttpRequest {
+    /// Streams the parts of a `multipart/form-data` body with the default limits.
+    ///
+    /// Takes the body, see [`HttpRequest::take_body_stream`]. Only on routes set up with
+    /// [`Server::stream_body`](crate::server::Server::stream_body) the body is read from the
+    /// connection part by part, otherwise it has already been read into memory as a whole.
+    pub fn multipart(&mut self) -> Result<Multipart<BodyStream>, MultipartError> {
+        self.multipart_with_limits(MultipartLimits::default())
+    }
+
+    pub fn multipart_with_limits(
+        &mut self,
+        limits: MultipartLimits,
+    ) -> Result<Multipart<BodyStream>, MultipartError> {
+        let content_type = self.headers.content_type();
+        let unsupported =
+            || MultipartError::UnsupportedContentType(content_type.map(str::to_owned));
+
+        let (mime, params) = content_type
+            .map(|content_type| content_type.split_once(';').unwrap_or((content_type, "")))
+            .ok_or_else(unsupported)?;
+        if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+            return Err(unsupported());
+        }
+
+        let boundary = parameters(params)
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+            .map(|(_, boundary)| boundary)
+            .ok_or(MultipartError::InvalidBoundary)?;
+
+        Multipart::with_limits(self.take_body_stream(), &boundary, limits)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The `name` and `filename` of a `form-data` disposition
+fn content_disposition(value: &str) -> Option<(String, Option<String>)> {
+    let (kind, params) = value.split_once(';')?;
+    if !kind.trim().eq_ignore_ascii_case("form-data") {
+        return None;
+    }
+
+    let mut name = None;
+    let mut filename = None;
+    for (key, value) in parameters(params) {
+        if key.eq_ignore_ascii_case("name") {
+            name = Some(value);
+        } else if key.eq_ignore_ascii_case("filename") {
+            filename = Some(value);
+        }
+    }
+
+    Some((name?, filename))
+}
+
+/// Parses `key=value` pairs separated by `;`, values may be quoted strings with `\` escapes
+fn parameters(params: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let mut rest = params;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ';' || c.is_ascii_whitespace());
+        let Some((key, value)) = rest.split_once('=') else {
+            return result;
+        };
+        let key = key.trim().to_owned();
+        let value = value.trim_start();
+
+        if let Some(quoted) = value.strip_prefix('"') {
+            let mut unquoted = String::new();
+            let mut chars = quoted.char_indices();
+            rest = "";
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        rest = &quoted[i + 1..];
+                        break;
+                    }
+                    '\\' => unquoted.extend(chars.next().map(|(_, c)| c)),
+                    c => unquoted.push(c),
+                }
+            }
+            result.push((key, unquoted));
+        } else {
+            let (value, remaining) = value.split_once(';').unwrap_or((value, ""));
+            result.push((key, value.trim().to_owned()));
+            rest = remaining;
+        }
+    }
+}
+
+impl Display for MultipartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MultipartError::UnsupportedContentType(Some(content_type)) => {
+                write!(f, "Expected a multipart body, got {content_type}")
+            }
+            MultipartError::UnsupportedContentType(None) => {
+                write!(f, "Expected a multipart body, got no content type")
+            }
+            MultipartError::InvalidBoundary => write!(f, "Missing or invalid multipart boundary"),
+            MultipartError::InvalidPartHeaders => write!(f, "Invalid multipart part headers"),
+            MultipartError::UnexpectedEnd => write!(f, "Multipart body ended unexpectedly"),
+            MultipartError::TooManyParts { max } => {
+                write!(f, "Multipart body has more than {max} parts")
+            }
+            MultipartError::HeadersTooLarge { max } => {
+                write!(f, "Multipart part headers larger than {max} bytes")
+            }
+            MultipartError::PartTooLarge { name, max } => {
+                write!(f, "Multipart part '{name}' larger than {max} bytes")
+            }
+            MultipartError::Io(e) => write!(f, "Io Error while reading multipart body: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MultipartError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Lets handlers use `?` while reading a multipart body
+impl From<MultipartError> for HttpError {
+    fn from(value: MultipartError) -> Self {
+        let status = match value {
+            MultipartError::UnsupportedContentType(_) => StatusCode::UnsupportedMediaType,
+            MultipartError::InvalidBoundary
+            | MultipartError::InvalidPartHeaders
+            | MultipartError::UnexpectedEnd => StatusCode::BadRequest,
+            MultipartError::TooManyParts { .. }
+            | MultipartError::HeadersTooLarge { .. }
+            | MultipartError::PartTooLarge { .. } => StatusCode::PayloadTooLarge,
+            MultipartError::Io(_) => StatusCode::InternalServerError,
+        };
+
+        HttpError::new(status, value.to_string())
+    }
+}
@@ -1,14 +1,39 @@
-use super::{header::HeaderMap, request::HttpRequest, HttpResponse, Method};
+use super::{
+    header::HeaderMap, request::HttpRequest, BodyStream, Extensions, HttpResponse, Method,
+};
 use crate::{
-    http::consts::{headers::CONTENT_LEN, CHUNK_END, HTTP_VER_STR},
-    url::parse_query_params_and_urldecode,
+    http::consts::{
+        headers::{CONTENT_LEN, TRANSFER_ENCODING},
+        CHUNK_END, HTTP_VER_STR,
+    },
+    transport::{Address, Transport},
+    url::{normalize_path, parse_query_params_and_urldecode, EncodedSlashes},
 };
 use std::{
     collections::HashMap,
-    io::{BufRead, Read, Write},
+    io::{self, BufRead, Read, Write},
 };
 
-pub(crate) fn parse_request(stream: &mut impl Read) -> Result<HttpRequest, super::Error> {
+/// Parses a whole request, the server reads the body separately with [`read_request_body`]
+#[cfg(test)]
+pub(crate) fn parse_request(
+    stream: &mut impl Read,
+    encoded_slashes: EncodedSlashes,
+) -> Result<HttpRequest, super::Error> {
+    let mut request = parse_request_head(stream, encoded_slashes)?;
+
+    if let Some(content_len) = request.headers.content_length() {
+        request.body = Some(read_body(stream, content_len)?);
+    }
+
+    Ok(request)
+}
+
+/// Parses the request line and headers, leaving the body unread
+pub(crate) fn parse_request_head(
+    stream: &mut impl Read,
+    encoded_slashes: EncodedSlashes,
+) -> Result<HttpRequest, super::Error> {
     let header_chunk = read_header(stream)?;
     let mut lines = header_chunk.lines();
 
@@ -47,21 +72,23 @@ pub(crate) fn parse_request(stream: &mut impl Read) -> Result<HttpRequest, super
     }
     let headers = HeaderMap { values: headers };
 
-    let body = if let Some(content_len) = headers.content_length() {
-        Some(read_body(stream, content_len)?)
-    } else {
-        None
-    };
-
     let (only_uri, queryparams) = parse_query_params_and_urldecode(&raw_uri);
+    let route = normalize_path(only_uri, encoded_slashes).map_err(super::Error::InvalidPath)?;
 
     Ok(HttpRequest {
         method,
         headers,
-        body,
-        route: only_uri.to_owned(),
+        body: None,
+        body_stream: None,
+        route,
         raw_route: raw_uri,
-        params: queryparams,
+        path_params: HashMap::new(),
+        matched_route: None,
+        query: queryparams,
+        peer_addr: Address::Unknown,
+        local_addr: Address::Unknown,
+        client_ip: None,
+        extensions: Extensions::new(),
     })
 }
 
@@ -99,6 +126,30 @@ fn read_body(stream: &mut impl Read, size: usize) -> Result<Vec<u8>, super::Erro
     Ok(buf)
 }
 
+/// Reads the body of a request parsed by [`parse_request_head`] into memory.
+///
+/// With `streaming` it is left on the connection instead, to be read
+/// through [`HttpRequest::take_body_stream`] while the handler runs.
+pub(crate) fn read_request_body<T: Transport>(
+    request: &mut HttpRequest,
+    stream: &mut T,
+    streaming: bool,
+) -> Result<(), super::Error> {
+    let Some(content_len) = request.headers.content_length() else {
+        return Ok(());
+    };
+
+    if streaming {
+        let len = content_len as u64;
+        let reader = stream.try_clone()?.take(len);
+        request.body_stream = Some(BodyStream::new(reader, Some(len)));
+    } else {
+        request.body = Some(read_body(stream, content_len)?);
+    }
+
+    Ok(())
+}
+
 pub(crate) fn write_response(
     mut stream: impl Write,
     mut response: HttpResponse,
@@ -110,6 +161,24 @@ pub(crate) fn write_response(
             .insert(CONTENT_LEN.to_owned(), body.len().to_string());
     }
 
+    let body_stream = response.stream.take();
+    match body_stream.as_ref().map(BodyStream::content_length) {
+        Some(Some(len)) => {
+            response
+                .headers
+                .values
+                .insert(CONTENT_LEN.to_owned(), len.to_string());
+        }
+        Some(None) => {
+            response.headers.values.remove(CONTENT_LEN);
+            response
+                .headers
+                .values
+                .insert(TRANSFER_ENCODING.to_owned(), "chunked".to_owned());
+        }
+        None => {}
+    }
+
     stream.write_all(format!("{} {}", HTTP_VER_STR, response.status).as_bytes())?;
 
     if !response.headers.values.is_empty() {
@@ -118,6 +187,17 @@ pub(crate) fn write_response(
         }
     }
 
+    if let Some(mut body) = body_stream {
+        stream.write_all(CHUNK_END)?;
+        return match body.content_length() {
+            Some(len) => {
+                io::copy(&mut (&mut body).take(len), &mut stream)?;
+                Ok(())
+            }
+            None => write_chunked(&mut stream, &mut body),
+        };
+    }
+
     if let Some(body) = response.body {
         stream.write_all(CHUNK_END)?;
         stream.write_all(&body)?;
@@ -127,3 +207,23 @@ pub(crate) fn write_response(
 
     Ok(())
 }
+
+fn write_chunked(stream: &mut impl Write, body: &mut impl Read) -> Result<(), super::Error> {
+    let mut buf = vec![0; 16 * 1024];
+
+    loop {
+        let read = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        stream.write_all(format!("{read:X}\r\n").as_bytes())?;
+        stream.write_all(&buf[..read])?;
+        stream.write_all(b"\r\n")?;
+    }
+
+    stream.write_all(b"0\r\n\r\n")?;
+    Ok(())
+}
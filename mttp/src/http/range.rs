@@ -0,0 +1,349 @@
+use super::{
+    consts::headers::{
+        ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+    },
+    parse_http_date, BodyStream, ETag, HttpRequest, HttpResponse, Method, StatusCode,
+};
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::{BuildHasher, Hasher},
+    io::{self, Cursor, Read, Seek, SeekFrom},
+};
+
+/// More ranges than this in one request are ignored and the full body is sent
+const MAX_RANGES: usize = 100;
+
+/// A range of bytes of a body, both ends inclusive like in `Content-Range`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// What a `Range` header asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ranges {
+    /// No (valid) `Range` header, the full body is sent with a 200
+    Full,
+    /// The requested ranges sorted, with overlapping and adjacent ones merged
+    Partial(Vec<ByteRange>),
+    /// None of the ranges are inside the body, answered with a 416
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Parses the value of a `Range` header for a body of `len` bytes.
+///
+/// Malformed headers and units other than `bytes` are ignored as RFC 9110 requires,
+/// so they result in [`Ranges::Full`].
+pub fn parse_range(header: &str, len: u64) -> Ranges {
+    let Some((unit, specs)) = header.split_once('=') else {
+        return Ranges::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Ranges::Full;
+    }
+
+    let specs = specs
+        .split(',')
+        .map(str::trim)
+        // Empty list elements are allowed by the list syntax
+        .filter(|spec| !spec.is_empty())
+        .collect::<Vec<_>>();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Ranges::Full;
+    }
+
+    let mut ranges = Vec::with_capacity(specs.len());
+    for spec in specs {
+        match parse_spec(spec, len) {
+            Some(range) => ranges.extend(range),
+            None => return Ranges::Full,
+        }
+    }
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    Ranges::Partial(merged)
+}
+
+/// The ranges `req` asks for from `response`, whose full body is `len` bytes long.
+///
+/// Only `GET` requests are served partially. An `If-Range` that doesn't match the `ETag` or
+/// `Last-Modified` of `response` means the client's copy is outdated, so it gets the full body.
+pub fn requested_ranges(req: &HttpRequest, response: &HttpResponse, len: u64) -> Ranges {
+    if req.method != Method::Get || response.status != StatusCode::Ok {
+        return Ranges::Full;
+    }
+    let Some(range) = req.headers.get(RANGE) else {
+        return Ranges::Full;
+    };
+
+    if let Some(if_range) = req.headers.get(IF_RANGE) {
+        let if_range = if_range.trim();
+        let matches = if if_range.starts_with('"') || if_range.starts_with("W/") {
+            // Only strong validators can be used for ranges
+            let etag = response
+                .headers
+                .get(ETAG)
+                .and_then(|etag| ETag::parse(etag));
+            ETag::parse(if_range).is_some_and(|tag| etag.is_some_and(|etag| tag.strong_eq(&etag)))
+        } else {
+            let modified = response.headers.get(LAST_MODIFIED);
+            parse_http_date(if_range)
+                .is_some_and(|date| modified.and_then(|m| parse_http_date(m)) == Some(date))
+        };
+
+        if !matches {
+            return Ranges::Full;
+        }
+    }
+
+    parse_range(range, len)
+}
+
+impl HttpResponse {
+    /// Answers a `Range` request for this response's body with a 206 or 416.
+    ///
+    /// Responses that aren't a 200 with a `body` are returned unchanged, others are marked
+    /// with `Accept-Ranges: bytes`. For bodies that are streamed from a file, use
+    /// [`HttpResponse::set_ranges`] with the file instead.
+    pub fn ranged(mut self, req: &HttpRequest) -> Self {
+        let Some(len) = self.body.as_ref().map(|body| body.len() as u64) else {
+            return self;
+        };
+        if self.status != StatusCode::Ok {
+            return self;
+        }
+
+        self.headers
+            .values
+            .insert(ACCEPT_RANGES.to_owned(), "bytes".to_owned());
+        let ranges = requested_ranges(req, &self, len);
+        if ranges == Ranges::Full {
+            return self;
+        }
+
+        let body = self.body.take().unwrap_or_default();
+        self.set_ranges(ranges, Cursor::new(body), len)
+            .expect("reading from memory can't fail");
+
+        if let Some(mut stream) = self.stream.take() {
+            let mut body = Vec::new();
+            stream
+                .read_to_end(&mut body)
+                .expect("reading from memory can't fail");
+            self.body = Some(body);
+        }
+        self
+    }
+
+    /// Makes this response the answer to `ranges` of the `len` bytes in `source`.
+    ///
+    /// A single range is sent as is with a `Content-Range`, several ones as `multipart/byteranges`
+    /// with the original `Content-Type` in every part. The body is streamed from `source`.
+    pub fn set_ranges<R: Read + Seek + Send + 'static>(
+        &mut self,
+        ranges: Ranges,
+        mut source: R,
+        len: u64,
+    ) -> io::Result<()> {
+        let headers = &mut self.headers.values;
+        headers.insert(ACCEPT_RANGES.to_owned(), "bytes".to_owned());
+
+        let ranges = match ranges {
+            Ranges::Full => {
+                self.body = None;
+                self.stream = Some(BodyStream::new(source, Some(len)));
+                return Ok(());
+            }
+            Ranges::Unsatisfiable => {
+                self.status = StatusCode::RangeNotSatisfiable;
+                self.body = None;
+                self.stream = None;
+                headers.remove(CONTENT_TYPE);
+                headers.insert(CONTENT_RANGE.to_owned(), format!("bytes */{len}"));
+                return Ok(());
+            }
+            Ranges::Partial(ranges) => ranges,
+        };
+
+        self.status = StatusCode::PartialContent;
+        self.body = None;
+
+        if let [range] = ranges[..] {
+            source.seek(SeekFrom::Start(range.start))?;
+            headers.insert(CONTENT_RANGE.to_owned(), content_range(range, len));
+            self.stream = Some(BodyStream::new(source.take(range.len()), Some(range.len())));
+            return Ok(());
+        }
+
+        let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+        let content_type = headers.remove(CONTENT_TYPE);
+        headers.insert(
+            CONTENT_TYPE.to_owned(),
+            format!("multipart/byteranges; boundary={boundary}"),
+        );
+
+        let mut pieces = VecDeque::new();
+        for (i, range) in ranges.into_iter().enumerate() {
+            let mut head = if i == 0 {
+                String::new()
+            } else {
+                "\r\n".to_owned()
+            };
+            head += &format!("--{boundary}\r\n");
+            if let Some(content_type) = &content_type {
+                head += &format!("{CONTENT_TYPE}: {content_type}\r\n");
+            }
+            head += &format!("{CONTENT_RANGE}: {}\r\n\r\n", content_range(range, len));
+
+            pieces.push_back(Piece::Bytes(Cursor::new(head.into_bytes())));
+            pieces.push_back(Piece::Range {
+                start: range.start,
+                remaining: range.len(),
+                seeked: false,
+            });
+        }
+        pieces.push_back(Piece::Bytes(Cursor::new(
+            format!("\r\n--{boundary}--\r\n").into_bytes(),
+        )));
+
+        let body_len = pieces.iter().map(Piece::len).sum();
+        self.stream = Some(BodyStream::new(
+            RangeReader { source, pieces },
+            Some(body_len),
+        ));
+        Ok(())
+    }
+}
+
+/// Parses `first-last`, `first-` or `-suffix`, `None` if it is malformed.
+/// The inner `None` is a valid range that lies outside the body.
+fn parse_spec(spec: &str, len: u64) -> Option<Option<ByteRange>> {
+    let (first, last) = spec.split_once('-')?;
+
+    let range = match (first.trim(), last.trim()) {
+        ("", suffix) => {
+            let suffix = number(suffix)?;
+            if suffix == 0 || len == 0 {
+                return Some(None);
+            }
+            ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            }
+        }
+        (first, last) => {
+            let start = number(first)?;
+            let end = match last {
+                "" => u64::MAX,
+                last => number(last)?,
+            };
+            if start > end {
+                return None;
+            }
+            if start >= len {
+                return Some(None);
+            }
+            ByteRange {
+                start,
+                end: end.min(len - 1),
+            }
+        }
+    };
+
+    Some(Some(range))
+}
+
+fn content_range(range: ByteRange, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start, range.end)
+}
+
+fn number(digits: &str) -> Option<u64> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Reads the parts of a `multipart/byteranges` body, seeking in `source` for each range
+struct RangeReader<R> {
+    source: R,
+    pieces: VecDeque<Piece>,
+}
+
+enum Piece {
+    Bytes(Cursor<Vec<u8>>),
+    Range {
+        start: u64,
+        remaining: u64,
+        seeked: bool,
+    },
+}
+
+impl Piece {
+    fn len(&self) -> u64 {
+        match self {
+            Piece::Bytes(bytes) => bytes.get_ref().len() as u64,
+            Piece::Range { remaining, .. } => *remaining,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for RangeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(piece) = self.pieces.front_mut() {
+            let n = match piece {
+                Piece::Bytes(bytes) => bytes.read(buf)?,
+                Piece::Range {
+                    start,
+                    remaining,
+                    seeked,
+                } => {
+                    if *remaining == 0 {
+                        0
+                    } else {
+                        if !*seeked {
+                            self.source.seek(SeekFrom::Start(*start))?;
+                            *seeked = true;
+                        }
+                        let max = buf
+                            .len()
+                            .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                        let n = self.source.read(&mut buf[..max])?;
+                        if n == 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        *remaining -= n as u64;
+                        n
+                    }
+                }
+            };
+
+            if n > 0 {
+                return Ok(n);
+            }
+            self.pieces.pop_front();
+        }
+
+        Ok(0)
+    }
+}
@@ -1,12 +1,196 @@
-use super::{header::HeaderMap, Method};
-use std::collections::HashMap;
+use super::{
+    header::HeaderMap, BodyStream, Extensions, HttpError, Method, QueryParams, StatusCode,
+};
+use crate::{json::JsonValue, transport::Address};
+use std::{
+    any::{type_name, Any},
+    collections::HashMap,
+    fmt::Display,
+    io::Cursor,
+    net::IpAddr,
+    str::FromStr,
+};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct HttpRequest {
     pub method: Method,
     pub raw_route: String,
     pub headers: HeaderMap,
     pub body: Option<Vec<u8>>,
+    /// The body if it is still unread, see [`HttpRequest::take_body_stream`]
+    pub(crate) body_stream: Option<BodyStream>,
     pub route: String,
-    pub params: HashMap<String, String>,
+    /// Parameters captured by the matched route pattern, like `id` in `/users/:id`
+    pub path_params: HashMap<String, String>,
+    /// The pattern of the route that matched, like `/users/:id`. `None` if no route did
+    pub matched_route: Option<String>,
+    /// The decoded query string parameters in order, including repeated keys
+    pub query: QueryParams,
+    /// The address of the directly connected peer (may be a proxy)
+    pub peer_addr: Address,
+    /// The address of the listener the request came in on
+    pub local_addr: Address,
+    /// The IP of the client that sent the request, resolved through trusted proxies
+    pub client_ip: Option<IpAddr>,
+    pub(crate) extensions: Extensions,
+}
+
+// Extensions are type erased and can't be compared, so they are left out
+impl PartialEq for HttpRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.method == other.method
+            && self.raw_route == other.raw_route
+            && self.headers == other.headers
+            && self.body == other.body
+            && self.body_stream == other.body_stream
+            && self.route == other.route
+            && self.path_params == other.path_params
+            && self.matched_route == other.matched_route
+            && self.query == other.query
+            && self.peer_addr == other.peer_addr
+            && self.local_addr == other.local_addr
+            && self.client_ip == other.client_ip
+    }
+}
+
+impl Eq for HttpRequest {}
+
+impl Default for HttpRequest {
+    fn default() -> Self {
+        Self {
+            method: Method::Get,
+            raw_route: String::new(),
+            headers: HeaderMap::empty(),
+            body: None,
+            body_stream: None,
+            route: String::new(),
+            path_params: HashMap::new(),
+            matched_route: None,
+            query: QueryParams::default(),
+            peer_addr: Address::Unknown,
+            local_addr: Address::Unknown,
+            client_ip: None,
+            extensions: Extensions::new(),
+        }
+    }
+}
+
+impl HttpRequest {
+    /// Parses the route parameter `name`.
+    ///
+    /// Fails with a 400 if the value can't be parsed, or a 500 if the route has no such parameter.
+    pub fn path_param<T: FromStr>(&self, name: &str) -> Result<T, HttpError>
+    where
+        T::Err: Display,
+    {
+        let value = self
+            .path_params
+            .get(name)
+            .ok_or_else(|| HttpError::internal(format!("Route has no parameter '{name}'")))?;
+
+        value
+            .parse()
+            .map_err(|e| HttpError::bad_request(format!("Invalid path parameter '{name}': {e}")))
+    }
+
+    /// Parses the query parameter `name`, failing with a 400 if it is missing or can't be parsed
+    pub fn query_param<T: FromStr>(&self, name: &str) -> Result<T, HttpError>
+    where
+        T::Err: Display,
+    {
+        self.optional_query_param(name)?
+            .ok_or_else(|| HttpError::bad_request(format!("Missing query parameter '{name}'")))
+    }
+
+    /// Like [`query_param`](Self::query_param), but a missing parameter is `None` instead of an error
+    pub fn optional_query_param<T: FromStr>(&self, name: &str) -> Result<Option<T>, HttpError>
+    where
+        T::Err: Display,
+    {
+        self.query
+            .get(name)
+            .map(|value| {
+                value.parse().map_err(|e| {
+                    HttpError::bad_request(format!("Invalid query parameter '{name}': {e}"))
+                })
+            })
+            .transpose()
+    }
+
+    /// The raw query string without the `?`, e.g. to parse it with other [`QueryOptions`](super::QueryOptions)
+    pub fn query_string(&self) -> &str {
+        self.raw_route
+            .split_once('?')
+            .map_or("", |(_, query)| query)
+    }
+
+    /// Parses the body as JSON.
+    ///
+    /// Fails with a 415 if the `Content-Type` isn't `application/json`, or a 400 if the body is invalid.
+    pub fn json(&self) -> Result<JsonValue, HttpError> {
+        self.expect_content_type("application/json")?;
+        JsonValue::parse(self.body_text()?)
+            .map_err(|e| HttpError::bad_request(format!("Invalid JSON body: {e}")))
+    }
+
+    /// Fails with a 415 unless the media type of the body is `expected` (parameters are ignored)
+    pub(crate) fn expect_content_type(&self, expected: &str) -> Result<(), HttpError> {
+        let mime = self
+            .headers
+            .content_type()
+            .and_then(|content_type| content_type.split(';').next())
+            .map(str::trim);
+
+        match mime {
+            Some(mime) if mime.eq_ignore_ascii_case(expected) => Ok(()),
+            _ => Err(HttpError::new(
+                StatusCode::UnsupportedMediaType,
+                format!("Expected content type {expected}"),
+            )),
+        }
+    }
+
+    /// The body as UTF-8 text, empty if there is none
+    pub(crate) fn body_text(&self) -> Result<&str, HttpError> {
+        std::str::from_utf8(self.body.as_deref().unwrap_or_default())
+            .map_err(|_| HttpError::bad_request("Request body is not valid UTF-8"))
+    }
+
+    /// Takes the body to read it as a stream.
+    ///
+    /// On routes set up with [`Server::stream_body`](crate::server::Server::stream_body) it is read
+    /// from the connection while it is consumed, so it is never held in memory as a whole.
+    /// Otherwise it comes from the already read [`body`](Self::body).
+    /// Either way the body can only be taken once, afterwards the stream is empty.
+    pub fn take_body_stream(&mut self) -> BodyStream {
+        if let Some(stream) = self.body_stream.take() {
+            return stream;
+        }
+
+        let body = self.body.take().unwrap_or_default();
+        let len = body.len() as u64;
+        BodyStream::new(Cursor::new(body), Some(len))
+    }
+
+    /// Values attached to this request by middlewares
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Returns the extension of type `T`.
+    ///
+    /// Fails with a 500, since a missing extension means the middleware providing it
+    /// isn't registered for this route.
+    pub fn extension<T: Any + Clone + Send + Sync>(&self) -> Result<&T, HttpError> {
+        self.extensions.get().ok_or_else(|| {
+            HttpError::internal(format!(
+                "No request extension of type {} was attached",
+                type_name::<T>()
+            ))
+        })
+    }
 }
@@ -1,25 +1,83 @@
 use super::{header::HeaderMap, StatusCode};
-use crate::http::consts::headers::CONTENT_TYPE;
+use crate::{http::consts::headers::CONTENT_TYPE, json::JsonValue};
+use std::{
+    fmt::Debug,
+    io::{self, Read},
+    sync::{Arc, Mutex},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HttpResponse {
     pub status: StatusCode,
     pub headers: HeaderMap,
     pub body: Option<Vec<u8>>,
+    /// A body that is sent while it is read, instead of `body`
+    pub stream: Option<BodyStream>,
 }
 
 pub struct HttpResponseBuilder {
     status: StatusCode,
     header: HeaderMap,
     body: Option<Vec<u8>>,
+    stream: Option<BodyStream>,
 }
 
+/// A response body that is read and sent piece by piece instead of being held in memory.
+///
+/// Without a known length it is sent with chunked transfer encoding.
+#[derive(Clone)]
+pub struct BodyStream {
+    reader: Arc<Mutex<dyn Read + Send>>,
+    len: Option<u64>,
+}
+
+impl BodyStream {
+    pub fn new(reader: impl Read + Send + 'static, len: Option<u64>) -> Self {
+        Self {
+            reader: Arc::new(Mutex::new(reader)),
+            len,
+        }
+    }
+
+    /// The number of bytes the stream will produce, if known
+    pub fn content_length(&self) -> Option<u64> {
+        self.len
+    }
+}
+
+impl Read for BodyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader
+            .lock()
+            .map_err(|_| io::Error::other("body stream was poisoned"))?
+            .read(buf)
+    }
+}
+
+impl Debug for BodyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// Streams are equal if they read from the same source
+impl PartialEq for BodyStream {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.reader, &other.reader) && self.len == other.len
+    }
+}
+
+impl Eq for BodyStream {}
+
 impl HttpResponse {
     pub fn builder() -> HttpResponseBuilder {
         HttpResponseBuilder {
             status: StatusCode::Ok,
             header: HeaderMap::empty(),
             body: None,
+            stream: None,
         }
     }
 
@@ -28,6 +86,7 @@ impl HttpResponse {
             status: StatusCode::Ok,
             headers: HeaderMap::empty(),
             body: None,
+            stream: None,
         }
     }
 
@@ -39,6 +98,60 @@ impl HttpResponse {
     }
 }
 
+/// Anything a handler can return as its response
+pub trait IntoResponse {
+    fn into_response(self) -> HttpResponse;
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self) -> HttpResponse {
+        self
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::builder().text(self).build()
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> HttpResponse {
+        self.to_owned().into_response()
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::builder().bytes(self).build()
+    }
+}
+
+/// An empty response with just the status
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::builder().status(self).build()
+    }
+}
+
+/// Replaces the status of the inner response
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> HttpResponse {
+        let mut response = self.1.into_response();
+        response.status = self.0;
+        response
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> HttpResponse {
+        match self {
+            Ok(ok) => ok.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
 impl HttpResponseBuilder {
     pub fn header(mut self, key: &str, value: String) -> Self {
         self.header.values.insert(key.to_owned(), value);
@@ -76,16 +189,29 @@ impl HttpResponseBuilder {
         self
     }
 
+    /// Sets a compact serialization of `json` as the body
+    pub fn json_value(self, json: &JsonValue) -> Self {
+        self.json(json.to_compact_string())
+    }
+
     pub fn bytes(mut self, bytes: Vec<u8>) -> Self {
         self.body = Some(bytes);
         self
     }
 
+    /// Sends the body while reading it from `stream`, replacing any other body
+    pub fn stream(mut self, stream: BodyStream) -> Self {
+        self.body = None;
+        self.stream = Some(stream);
+        self
+    }
+
     pub fn build(self) -> HttpResponse {
         HttpResponse {
             status: self.status,
             headers: self.header,
             body: self.body,
+            stream: self.stream,
         }
     }
 }
@@ -1,11 +1,29 @@
-use super::protocol::parse_request;
-use crate::http::{HeaderMap, HttpRequest, Method};
-use std::{collections::HashMap, io::Cursor};
+use super::{
+    forwarded::client_ip,
+    protocol::{parse_request, write_response},
+};
+use crate::{
+    compress::{compress, Format},
+    http::{
+        format_http_date, negotiate_encoding, parse_http_date, parse_range, BodyStream, ByteRange,
+        ContentCoding, ETag, Extensions, FormError, HeaderMap, HttpError, HttpRequest,
+        HttpResponse, IntoResponse, Method, Multipart, MultipartError, MultipartLimits, PartData,
+        Preconditions, QueryParams, Ranges, StatusCode,
+    },
+    transport::Address,
+    url::EncodedSlashes,
+};
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, UNIX_EPOCH},
+};
 
 #[test]
 pub fn test_request1() {
     let x = b"GET /test1/test2?real=fake HTTP/1.1\r\nTest: Test\r\n\r\n";
-    let got = parse_request(&mut x.as_slice()).unwrap();
+    let got = parse_request(&mut x.as_slice(), EncodedSlashes::Reject).unwrap();
 
     assert_eq!(
         got,
@@ -14,8 +32,15 @@ pub fn test_request1() {
             raw_route: "/test1/test2?real=fake".to_owned(),
             headers: HeaderMap::from([("Test", "Test")]),
             body: None,
+            body_stream: None,
             route: "/test1/test2".to_owned(),
-            params: HashMap::from([("real".to_owned(), "fake".to_owned())])
+            path_params: HashMap::new(),
+            matched_route: None,
+            query: QueryParams::from([("real", "fake")]),
+            peer_addr: Address::Unknown,
+            local_addr: Address::Unknown,
+            client_ip: None,
+            extensions: Extensions::new(),
         }
     )
 }
@@ -24,7 +49,7 @@ pub fn test_request1() {
 pub fn test_request2() {
     let x =
         b"GET /test1/test2?real=fake HTTP/1.1\r\nTest: Test\r\nContent-Length: 27\r\n\r\nTHIS IS A TEST \n\0\0TEST TEST";
-    let got = parse_request(&mut x.as_slice()).unwrap();
+    let got = parse_request(&mut x.as_slice(), EncodedSlashes::Reject).unwrap();
 
     assert_eq!(
         got,
@@ -33,8 +58,15 @@ pub fn test_request2() {
             raw_route: "/test1/test2?real=fake".to_owned(),
             headers: HeaderMap::from([("Test", "Test"), ("Content-Length", "27")]),
             body: Some(b"THIS IS A TEST \n\0\0TEST TEST".to_vec()),
+            body_stream: None,
             route: "/test1/test2".to_owned(),
-            params: HashMap::from([("real".to_owned(), "fake".to_owned())])
+            path_params: HashMap::new(),
+            matched_route: None,
+            query: QueryParams::from([("real", "fake")]),
+            peer_addr: Address::Unknown,
+            local_addr: Address::Unknown,
+            client_ip: None,
+            extensions: Extensions::new(),
         }
     )
 }
@@ -43,7 +75,7 @@ pub fn test_request2() {
 pub fn test_request3() {
     let x =
         b"GET /test1/test2?real=fake HTTP/1.1\r\nTest: Test\r\nContent-Length: 20\r\n\r\nTHIS IS A TEST \n\0\0TEST TEST";
-    let got = parse_request(&mut x.as_slice()).unwrap();
+    let got = parse_request(&mut x.as_slice(), EncodedSlashes::Reject).unwrap();
 
     assert_eq!(
         got,
@@ -52,8 +84,15 @@ pub fn test_request3() {
             raw_route: "/test1/test2?real=fake".to_owned(),
             headers: HeaderMap::from([("Test", "Test"), ("Content-Length", "20")]),
             body: Some(b"THIS IS A TEST \n\0\0TE".to_vec()),
+            body_stream: None,
             route: "/test1/test2".to_owned(),
-            params: HashMap::from([("real".to_owned(), "fake".to_owned())])
+            path_params: HashMap::new(),
+            matched_route: None,
+            query: QueryParams::from([("real", "fake")]),
+            peer_addr: Address::Unknown,
+            local_addr: Address::Unknown,
+            client_ip: None,
+            extensions: Extensions::new(),
         }
     )
 }
@@ -61,15 +100,10 @@ pub fn test_request3() {
 #[test]
 pub fn test_request4() {
     let x = b"GET /\0\0";
-    let got = parse_request(&mut x.as_slice());
+    let got = parse_request(&mut x.as_slice(), EncodedSlashes::Reject);
 
     match got {
-        Err(e) => match e {
-            crate::http::Error::UnsupportedVersion => {}
-            _ => {
-                panic!("Wrong error");
-            }
-        },
+        Err(crate::http::Error::UnsupportedVersion) => {}
         _ => {
             panic!("Wrong error");
         }
@@ -79,7 +113,7 @@ pub fn test_request4() {
 #[test]
 pub fn test_request5() {
     let x = b"GET / HTTP/1.2";
-    let got = parse_request(&mut x.as_slice());
+    let got = parse_request(&mut x.as_slice(), EncodedSlashes::Reject);
 
     assert!(got.is_err());
 }
@@ -89,7 +123,7 @@ fn test_parse_request_with_empty_headers() {
     let request_data = b"GET /some/route HTTP/1.1\r\n\r\n";
     let mut cursor = Cursor::new(request_data);
 
-    let result = parse_request(&mut cursor);
+    let result = parse_request(&mut cursor, EncodedSlashes::Reject);
 
     assert!(result.is_ok());
     let request = result.unwrap();
@@ -98,7 +132,7 @@ fn test_parse_request_with_empty_headers() {
     assert!(request.headers.is_empty());
     assert!(request.body.is_none());
     assert_eq!(request.route, "/some/route");
-    assert!(request.params.is_empty());
+    assert!(request.query.is_empty());
 }
 
 #[test]
@@ -106,7 +140,7 @@ fn test_parse_request_with_missing_method() {
     let request_data = b"/some/route HTTP/1.1\r\nHost: example.com\r\n\r\n";
     let mut cursor = Cursor::new(request_data);
 
-    let result = parse_request(&mut cursor);
+    let result = parse_request(&mut cursor, EncodedSlashes::Reject);
 
     assert!(result.is_err()); // Expecting an error for missing method
 }
@@ -116,7 +150,7 @@ fn test_parse_invalid_http_request() {
     let request_data = b"INVALID REQUEST FORMAT";
     let mut cursor = Cursor::new(request_data);
 
-    let result = parse_request(&mut cursor);
+    let result = parse_request(&mut cursor, EncodedSlashes::Reject);
 
     assert!(result.is_err()); // Expecting an error for invalid format
 }
@@ -126,7 +160,7 @@ fn test_parse_request_with_body() {
     let request_data = b"POST /some/route HTTP/1.1\r\nHost: example.com\r\nContent-Length: 13\r\n\r\nHello, world!";
     let mut cursor = Cursor::new(request_data);
 
-    let result = parse_request(&mut cursor);
+    let result = parse_request(&mut cursor, EncodedSlashes::Reject);
 
     assert!(result.is_ok());
     let request = result.unwrap();
@@ -135,7 +169,7 @@ fn test_parse_request_with_body() {
     assert_eq!(request.headers.get("Host").unwrap(), "example.com");
     assert_eq!(request.body.as_deref(), Some(b"Hello, world!".as_ref()));
     assert_eq!(request.route, "/some/route");
-    assert!(request.params.is_empty());
+    assert!(request.query.is_empty());
 }
 
 #[test]
@@ -143,7 +177,7 @@ fn test_parse_valid_http_request() {
     let request_data = b"GET /some/route HTTP/1.1\r\nHost: example.com\r\n\r\n";
     let mut cursor = Cursor::new(request_data);
 
-    let result = parse_request(&mut cursor);
+    let result = parse_request(&mut cursor, EncodedSlashes::Reject);
 
     assert!(result.is_ok());
     let request = result.unwrap();
@@ -152,5 +186,955 @@ fn test_parse_valid_http_request() {
     assert_eq!(request.headers.get("Host").unwrap(), "example.com");
     assert!(request.body.is_none());
     assert_eq!(request.route, "/some/route"); // Assuming route parsing is just the raw route
-    assert!(request.params.is_empty());
+    assert!(request.query.is_empty());
+}
+
+fn tcp(addr: &str) -> Address {
+    Address::Tcp(addr.parse::<SocketAddr>().unwrap())
+}
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn test_client_ip_without_trusted_proxy() {
+    let headers = HeaderMap::from([("X-Forwarded-For", "1.1.1.1")]);
+
+    assert_eq!(
+        client_ip(tcp("10.0.0.1:4000"), &headers, &[], true),
+        Some(ip("10.0.0.1"))
+    );
+}
+
+#[test]
+fn test_client_ip_from_x_forwarded_for() {
+    let headers = HeaderMap::from([("X-Forwarded-For", "6.6.6.6, 1.1.1.1, 10.0.0.2")]);
+    let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+    // 6.6.6.6 could have been made up by the client, 1.1.1.1 is the last untrusted hop
+    assert_eq!(
+        client_ip(tcp("10.0.0.1:4000"), &headers, &trusted, true),
+        Some(ip("1.1.1.1"))
+    );
+}
+
+#[test]
+fn test_client_ip_from_forwarded() {
+    let headers = HeaderMap::from([(
+        "Forwarded",
+        r#"for=192.0.2.43, for="[2001:db8:cafe::17]:4711";proto=https, For=10.0.0.2"#,
+    )]);
+    let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+    assert_eq!(
+        client_ip(tcp("10.0.0.1:4000"), &headers, &trusted, true),
+        Some(ip("2001:db8:cafe::17"))
+    );
+}
+
+#[test]
+fn test_client_ip_stops_at_obfuscated_hop() {
+    let headers = HeaderMap::from([("Forwarded", "for=192.0.2.43, for=_hidden")]);
+
+    assert_eq!(
+        client_ip(tcp("10.0.0.1:4000"), &headers, &[ip("10.0.0.1")], true),
+        Some(ip("10.0.0.1"))
+    );
+}
+
+#[test]
+fn test_client_ip_over_unix_socket() {
+    let headers = HeaderMap::from([("X-Forwarded-For", "1.1.1.1")]);
+
+    assert_eq!(
+        client_ip(Address::Unix, &headers, &[], true),
+        Some(ip("1.1.1.1"))
+    );
+    assert_eq!(
+        client_ip(Address::Unix, &HeaderMap::empty(), &[], true),
+        None
+    );
+
+    // The socket may be reachable by untrusted local processes
+    assert_eq!(client_ip(Address::Unix, &headers, &[], false), None);
+}
+
+#[test]
+fn test_client_ip_forwarded_without_for() {
+    // The proxy in front of 10.0.0.2 didn't report who connected to it
+    let headers = HeaderMap::from([("Forwarded", "for=6.6.6.6, proto=https, for=10.0.0.2")]);
+    let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+    assert_eq!(
+        client_ip(tcp("10.0.0.1:4000"), &headers, &trusted, true),
+        Some(ip("10.0.0.2"))
+    );
+}
+
+#[test]
+fn test_query_and_path_params_are_separate() {
+    let mut request = parse_request(
+        &mut b"GET /person/5?id=9 HTTP/1.1\r\n\r\n".as_slice(),
+        EncodedSlashes::Reject,
+    )
+    .unwrap();
+    request.path_params.insert("id".to_owned(), "5".to_owned());
+
+    assert_eq!(request.path_param::<u64>("id"), Ok(5));
+    assert_eq!(request.query_param::<u64>("id"), Ok(9));
+}
+
+#[test]
+fn test_typed_param_errors() {
+    let mut request = parse_request(
+        &mut b"GET /person/abc?page=x HTTP/1.1\r\n\r\n".as_slice(),
+        EncodedSlashes::Reject,
+    )
+    .unwrap();
+    request
+        .path_params
+        .insert("id".to_owned(), "abc".to_owned());
+
+    let status = |result: Result<u64, HttpError>| result.unwrap_err().status;
+    assert_eq!(status(request.path_param("id")), StatusCode::BadRequest);
+    assert_eq!(
+        status(request.path_param("nope")),
+        StatusCode::InternalServerError
+    );
+    assert_eq!(status(request.query_param("page")), StatusCode::BadRequest);
+    assert_eq!(
+        status(request.query_param("missing")),
+        StatusCode::BadRequest
+    );
+
+    assert_eq!(request.optional_query_param::<u64>("missing"), Ok(None));
+    assert!(request.optional_query_param::<u64>("page").is_err());
+}
+
+#[test]
+fn test_extensions() {
+    #[derive(Debug, Clone, PartialEq)]
+    struct User(&'static str);
+
+    let mut extensions = Extensions::new();
+    assert_eq!(extensions.insert(User("a")), None);
+    assert_eq!(extensions.insert(User("b")), Some(User("a")));
+    assert_eq!(extensions.insert(5u32), None);
+
+    *extensions.get_mut::<u32>().unwrap() += 1;
+    let cloned = extensions.clone();
+
+    assert_eq!(extensions.remove::<User>(), Some(User("b")));
+    assert_eq!(extensions.get::<User>(), None);
+    assert_eq!(cloned.get::<User>(), Some(&User("b")));
+    assert_eq!(cloned.get::<u32>(), Some(&6));
+}
+
+#[test]
+fn test_request_extension() {
+    #[derive(Debug, Clone, PartialEq)]
+    struct RequestId(u64);
+
+    let mut request = parse_request(
+        &mut b"GET / HTTP/1.1\r\n\r\n".as_slice(),
+        EncodedSlashes::Reject,
+    )
+    .unwrap();
+    assert_eq!(
+        request.extension::<RequestId>().unwrap_err().status,
+        StatusCode::InternalServerError
+    );
+
+    request
+        .extensions_mut()
+        .get_or_insert_with(|| RequestId(1))
+        .0 += 1;
+    request
+        .extensions_mut()
+        .get_or_insert_with(|| RequestId(10))
+        .0 += 1;
+
+    assert!(request.extensions().contains::<RequestId>());
+    assert_eq!(request.extensions().len(), 1);
+    assert_eq!(request.extension::<RequestId>(), Ok(&RequestId(3)));
+}
+
+#[test]
+fn test_into_response() {
+    let response = (StatusCode::Created, "made").into_response();
+    assert_eq!(response.status, StatusCode::Created);
+    assert_eq!(response.body.as_deref(), Some(b"made".as_ref()));
+    assert_eq!(response.headers.content_type(), Some("text/plain"));
+
+    assert_eq!(StatusCode::NoContent.into_response().body, None);
+    assert_eq!(vec![1, 2].into_response().body, Some(vec![1, 2]));
+
+    let failed: Result<String, HttpError> = Err(HttpError::bad_request("nope"));
+    let response = failed.into_response();
+    assert_eq!(response.status, StatusCode::BadRequest);
+    assert_eq!(response.body.as_deref(), Some(b"nope".as_ref()));
+}
+
+fn form_request(content_type: &str, body: &[u8]) -> HttpRequest {
+    let mut request = HttpRequest {
+        body: Some(body.to_vec()),
+        ..Default::default()
+    };
+    request
+        .headers
+        .values
+        .insert("Content-Type".to_owned(), content_type.to_owned());
+    request
+}
+
+#[test]
+fn test_form() {
+    let request = form_request(
+        "application/x-www-form-urlencoded; charset=UTF-8",
+        b"name=John+Doe&tag=a&city=New%20York&tag=b&empty=&flag&",
+    );
+    let form = request.form().unwrap();
+
+    assert_eq!(form.get("name"), Some("John Doe"));
+    assert_eq!(form.get("city"), Some("New York"));
+    assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["a", "b"]);
+    assert_eq!(form.get("empty"), Some(""));
+    assert_eq!(form.get("flag"), Some(""));
+    assert_eq!(form.len(), 6);
+    assert_eq!(
+        form.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+        ["name", "tag", "city", "tag", "empty", "flag"]
+    );
+    assert_eq!(form.to_map().get("tag").map(String::as_str), Some("b"));
+}
+
+#[test]
+fn test_form_errors() {
+    let request = form_request("application/json", b"a=1");
+    assert_eq!(
+        request.form(),
+        Err(FormError::UnsupportedContentType(Some(
+            "application/json".to_owned()
+        )))
+    );
+    assert_eq!(
+        HttpError::from(request.form().unwrap_err()).status,
+        StatusCode::UnsupportedMediaType
+    );
+
+    let request = form_request("application/x-www-form-urlencoded", b"a=%zz&b=1");
+    assert_eq!(
+        request.form(),
+        Err(FormError::InvalidField("a=%zz".to_owned()))
+    );
+
+    let request = form_request("application/x-www-form-urlencoded", b"a=%FF");
+    assert_eq!(
+        HttpError::from(request.form().unwrap_err()).status,
+        StatusCode::BadRequest
+    );
+
+    let request = form_request("application/x-www-form-urlencoded", b"a=\xFF");
+    assert_eq!(request.form(), Err(FormError::InvalidUtf8));
+}
+
+const MULTIPART_BODY: &[u8] = b"preamble\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\
+\r\n\
+Hello\r\nWorld\r\n\
+--XyZ  \r\n\
+content-disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+line\r\n--Xy is not a boundary\r\n--X\r\n\
+--XyZ--\r\n\
+epilogue";
+
+/// Hands out the data a few bytes at a time, like a slow connection
+struct Trickle<'a>(&'a [u8]);
+
+impl std::io::Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.0.len()).min(3);
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_multipart() {
+    let mut request = form_request("multipart/form-data; boundary=\"XyZ\"", MULTIPART_BODY);
+    let parts = request
+        .multipart()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].name, "title");
+    assert_eq!(parts[0].filename, None);
+    assert_eq!(parts[0].text(), Some("Hello\r\nWorld"));
+
+    assert_eq!(parts[1].name, "upload");
+    assert!(parts[1].is_file());
+    assert_eq!(parts[1].filename.as_deref(), Some("a \"b\".txt"));
+    assert_eq!(parts[1].content_type.as_deref(), Some("text/plain"));
+    assert_eq!(
+        parts[1].text(),
+        Some("line\r\n--Xy is not a boundary\r\n--X")
+    );
+}
+
+#[test]
+fn test_multipart_spills_to_file() {
+    let limits = MultipartLimits {
+        memory_threshold: 8,
+        ..Default::default()
+    };
+    let mut multipart = Multipart::with_limits(Trickle(MULTIPART_BODY), "XyZ", limits).unwrap();
+
+    let field = multipart.next_part().unwrap().unwrap();
+    assert!(matches!(field.data, PartData::Memory(_)));
+    assert_eq!(field.text(), Some("Hello\r\nWorld"));
+
+    let file = multipart.next_part().unwrap().unwrap();
+    let PartData::File(temp) = &file.data else {
+        panic!("expected the file part to be spilled");
+    };
+    let path = temp.path().to_owned();
+    assert_eq!(temp.len(), 33);
+    assert_eq!(
+        file.bytes().unwrap(),
+        b"line\r\n--Xy is not a boundary\r\n--X"
+    );
+    assert!(multipart.next_part().unwrap().is_none());
+
+    drop(file);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_multipart_errors() {
+    let parse = |content_type: &str, body: &[u8], limits: MultipartLimits| {
+        form_request(content_type, body)
+            .multipart_with_limits(limits)
+            .and_then(|parts| parts.collect::<Result<Vec<_>, _>>())
+            .unwrap_err()
+    };
+    let content_type = "multipart/form-data; boundary=XyZ";
+    let defaults = MultipartLimits::default();
+
+    let error = parse("text/plain", MULTIPART_BODY, defaults);
+    assert!(matches!(
+        error,
+        MultipartError::UnsupportedContentType(Some(_))
+    ));
+    assert_eq!(
+        HttpError::from(error).status,
+        StatusCode::UnsupportedMediaType
+    );
+
+    let error = parse("multipart/form-data", MULTIPART_BODY, defaults);
+    assert!(matches!(error, MultipartError::InvalidBoundary));
+
+    let error = parse(content_type, &MULTIPART_BODY[..60], defaults);
+    assert!(matches!(error, MultipartError::UnexpectedEnd));
+
+    let error = parse(
+        content_type,
+        b"--XyZ\r\nContent-Disposition: form-data\r\n\r\nx\r\n--XyZ--",
+        defaults,
+    );
+    assert!(matches!(error, MultipartError::InvalidPartHeaders));
+
+    let limits = MultipartLimits {
+        max_parts: 1,
+        ..Default::default()
+    };
+    let error = parse(content_type, MULTIPART_BODY, limits);
+    assert!(matches!(error, MultipartError::TooManyParts { max: 1 }));
+    assert_eq!(HttpError::from(error).status, StatusCode::PayloadTooLarge);
+
+    let limits = MultipartLimits {
+        max_field_size: 4,
+        ..Default::default()
+    };
+    let error = parse(content_type, MULTIPART_BODY, limits);
+    assert!(matches!(error, MultipartError::PartTooLarge { ref name, max: 4 } if name == "title"));
+
+    let limits = MultipartLimits {
+        max_header_size: 16,
+        ..Default::default()
+    };
+    let error = parse(content_type, MULTIPART_BODY, limits);
+    assert!(matches!(error, MultipartError::HeadersTooLarge { max: 16 }));
+}
+
+#[test]
+fn test_request_path_is_normalized() {
+    let request = parse_request(
+        &mut b"GET /static/./css/%2E%2E/app%20v2.js?v=1 HTTP/1.1\r\n\r\n".as_slice(),
+        EncodedSlashes::Reject,
+    )
+    .unwrap();
+    assert_eq!(request.route, "/static/app v2.js");
+    assert_eq!(request.raw_route, "/static/./css/%2E%2E/app%20v2.js?v=1");
+
+    let request = parse_request(
+        &mut b"GET /a%2Fb HTTP/1.1\r\n\r\n".as_slice(),
+        EncodedSlashes::Reject,
+    );
+    assert!(matches!(request, Err(crate::http::Error::InvalidPath(_))));
+}
+
+#[test]
+fn test_http_date() {
+    let time = UNIX_EPOCH + Duration::from_secs(784111777);
+    assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+
+    assert_eq!(
+        format_http_date(UNIX_EPOCH),
+        "Thu, 01 Jan 1970 00:00:00 GMT"
+    );
+    let leap_day = UNIX_EPOCH + Duration::from_secs(951782400);
+    assert_eq!(format_http_date(leap_day), "Tue, 29 Feb 2000 00:00:00 GMT");
+    assert_eq!(parse_http_date(&format_http_date(leap_day)), Some(leap_day));
+
+    for invalid in [
+        "Sunday, 06-Nov-94 08:49:37 GMT",
+        "Sun Nov  6 08:49:37 1994",
+        "Sun, 06 Nov 1994 08:49:37 UTC",
+        "Sun, 6 Nov 1994 08:49:37 GMT",
+        "Sun, 06 Nov 1994 24:00:00 GMT",
+    ] {
+        assert_eq!(parse_http_date(invalid), None, "{invalid}");
+    }
+}
+
+#[test]
+fn test_write_stream() {
+    let response = HttpResponse::builder()
+        .stream(BodyStream::new(Cursor::new(b"hello".to_vec()), Some(5)))
+        .build();
+    let mut out = Vec::new();
+    write_response(&mut out, response).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("Content-Length: 5\r\n"));
+    assert!(out.ends_with("\r\n\r\nhello"));
+
+    let response = HttpResponse::builder()
+        .stream(BodyStream::new(Cursor::new(b"hello".to_vec()), None))
+        .build();
+    let mut out = Vec::new();
+    write_response(&mut out, response).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!out.contains("Content-Length"));
+    assert!(out.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"));
+}
+
+fn ranges<const N: usize>(ranges: [(u64, u64); N]) -> Ranges {
+    Ranges::Partial(
+        ranges
+            .into_iter()
+            .map(|(start, end)| ByteRange { start, end })
+            .collect(),
+    )
+}
+
+#[test]
+fn test_parse_range() {
+    assert_eq!(parse_range("bytes=0-499", 1000), ranges([(0, 499)]));
+    assert_eq!(parse_range("bytes=500-", 1000), ranges([(500, 999)]));
+    assert_eq!(parse_range("bytes=-200", 1000), ranges([(800, 999)]));
+    assert_eq!(parse_range("bytes=-2000", 1000), ranges([(0, 999)]));
+    assert_eq!(parse_range("bytes=900-1999", 1000), ranges([(900, 999)]));
+    assert_eq!(
+        parse_range("bytes=0-0, -1", 1000),
+        ranges([(0, 0), (999, 999)])
+    );
+    // Sorted and merged
+    assert_eq!(
+        parse_range("bytes=500-600,0-99,100-199,550-700", 1000),
+        ranges([(0, 199), (500, 700)])
+    );
+    // Ranges outside the body are dropped as long as one is left
+    assert_eq!(parse_range("bytes=0-9,2000-", 1000), ranges([(0, 9)]));
+
+    for unsatisfiable in [
+        "bytes=1000-",
+        "bytes=1000-2000",
+        "bytes=-0",
+        "bytes=5000-,2000-",
+    ] {
+        assert_eq!(parse_range(unsatisfiable, 1000), Ranges::Unsatisfiable);
+    }
+    assert_eq!(parse_range("bytes=-5", 0), Ranges::Unsatisfiable);
+
+    for invalid in [
+        "bytes=",
+        "bytes=a-b",
+        "bytes=5-1",
+        "bytes=1",
+        "items=0-5",
+        "0-5",
+    ] {
+        assert_eq!(parse_range(invalid, 1000), Ranges::Full, "{invalid}");
+    }
+    let many = format!("bytes={}", vec!["0-1"; 101].join(","));
+    assert_eq!(parse_range(&many, 1000), Ranges::Full);
+}
+
+fn range_request<const N: usize>(headers: [(&str, &str); N]) -> HttpRequest {
+    HttpRequest {
+        headers: HeaderMap::from(headers),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_ranged_response() {
+    let full = || {
+        HttpResponse::builder()
+            .text("0123456789".to_owned())
+            .header("ETag", "\"v1\"".to_owned())
+            .build()
+    };
+
+    let response = full().ranged(&range_request([]));
+    assert_eq!(response.status, StatusCode::Ok);
+    assert_eq!(response.headers.get("Accept-Ranges").unwrap(), "bytes");
+
+    let response = full().ranged(&range_request([("Range", "bytes=2-4")]));
+    assert_eq!(response.status, StatusCode::PartialContent);
+    assert_eq!(response.body.as_deref(), Some(b"234".as_ref()));
+    assert_eq!(
+        response.headers.get("Content-Range").unwrap(),
+        "bytes 2-4/10"
+    );
+    assert_eq!(response.headers.get("Content-Type").unwrap(), "text/plain");
+
+    let response = full().ranged(&range_request([("Range", "bytes=20-")]));
+    assert_eq!(response.status, StatusCode::RangeNotSatisfiable);
+    assert_eq!(response.headers.get("Content-Range").unwrap(), "bytes */10");
+    assert_eq!(response.body, None);
+
+    let response = full().ranged(&range_request([("Range", "bytes=0-1,-2")]));
+    assert_eq!(response.status, StatusCode::PartialContent);
+    let content_type = response.headers.get("Content-Type").unwrap();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap();
+    let expected = format!(
+        "--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+         \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+         \r\n--{boundary}--\r\n"
+    );
+    assert_eq!(String::from_utf8(response.body.unwrap()).unwrap(), expected);
+}
+
+#[test]
+fn test_if_range() {
+    let full = || {
+        HttpResponse::builder()
+            .bytes(b"0123456789".to_vec())
+            .header("ETag", "\"v1\"".to_owned())
+            .header("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT".to_owned())
+            .build()
+    };
+    let status = |if_range| {
+        full()
+            .ranged(&range_request([
+                ("Range", "bytes=0-0"),
+                ("If-Range", if_range),
+            ]))
+            .status
+    };
+
+    assert_eq!(status("\"v1\""), StatusCode::PartialContent);
+    assert_eq!(status("\"v2\""), StatusCode::Ok);
+    assert_eq!(status("W/\"v1\""), StatusCode::Ok);
+    assert_eq!(
+        status("Sun, 06 Nov 1994 08:49:37 GMT"),
+        StatusCode::PartialContent
+    );
+    assert_eq!(status("Sun, 06 Nov 1994 08:49:38 GMT"), StatusCode::Ok);
+
+    // Only GET requests get partial responses
+    let mut req = range_request([("Range", "bytes=0-0")]);
+    req.method = Method::Post;
+    assert_eq!(full().ranged(&req).status, StatusCode::Ok);
+}
+
+#[test]
+fn test_etag() {
+    assert_eq!(ETag::parse("\"abc\""), Some(ETag::strong("abc")));
+    assert_eq!(ETag::parse(" W/\"abc\" "), Some(ETag::weak("abc")));
+    assert_eq!(ETag::parse("\"\""), Some(ETag::strong("")));
+    for invalid in ["abc", "\"abc", "w/\"abc\"", "\"a\"b\"", "\"a b\""] {
+        assert_eq!(ETag::parse(invalid), None, "{invalid}");
+    }
+
+    assert_eq!(ETag::strong("v1").to_string(), "\"v1\"");
+    assert_eq!(ETag::strong("v1").into_weak().to_string(), "W/\"v1\"");
+
+    assert!(ETag::strong("v1").strong_eq(&ETag::strong("v1")));
+    assert!(!ETag::strong("v1").strong_eq(&ETag::weak("v1")));
+    assert!(ETag::strong("v1").weak_eq(&ETag::weak("v1")));
+    assert!(!ETag::weak("v1").weak_eq(&ETag::weak("v2")));
+
+    assert_eq!(ETag::from_body(b"hello"), ETag::from_body(b"hello"));
+    assert_ne!(ETag::from_body(b"hello"), ETag::from_body(b"hellp"));
+    assert!(!ETag::from_body(b"").weak);
+}
+
+#[test]
+fn test_preconditions() {
+    let modified = UNIX_EPOCH + Duration::from_secs(784111777);
+    let etag = ETag::strong("v1");
+    let evaluate = |method, headers: &[(&str, &str)]| {
+        let mut req = HttpRequest {
+            method,
+            ..Default::default()
+        };
+        for (key, value) in headers {
+            req.headers
+                .values
+                .insert(key.to_string(), value.to_string());
+        }
+        Preconditions::from_request(&req).evaluate(Some(&etag), Some(modified))
+    };
+    let before = "Sun, 06 Nov 1994 08:49:36 GMT";
+    let same = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    assert_eq!(evaluate(Method::Get, &[]), None);
+
+    let not_modified = Some(StatusCode::NotModified);
+    let failed = Some(StatusCode::PreconditionFailed);
+
+    assert_eq!(
+        evaluate(Method::Get, &[("If-None-Match", "\"v1\"")]),
+        not_modified
+    );
+    assert_eq!(
+        evaluate(Method::Get, &[("If-None-Match", "W/\"v1\"")]),
+        not_modified
+    );
+    assert_eq!(
+        evaluate(Method::Get, &[("If-None-Match", "\"a\", \"v1\"")]),
+        not_modified
+    );
+    assert_eq!(
+        evaluate(Method::Get, &[("If-None-Match", "*")]),
+        not_modified
+    );
+    assert_eq!(evaluate(Method::Get, &[("If-None-Match", "\"v2\"")]), None);
+    assert_eq!(evaluate(Method::Put, &[("If-None-Match", "*")]), failed);
+
+    assert_eq!(
+        evaluate(Method::Get, &[("If-Modified-Since", same)]),
+        not_modified
+    );
+    assert_eq!(
+        evaluate(Method::Get, &[("If-Modified-Since", before)]),
+        None
+    );
+    assert_eq!(
+        evaluate(Method::Get, &[("If-Modified-Since", "yesterday")]),
+        None
+    );
+    assert_eq!(evaluate(Method::Post, &[("If-Modified-Since", same)]), None);
+    // If-None-Match takes precedence
+    assert_eq!(
+        evaluate(
+            Method::Get,
+            &[("If-None-Match", "\"v2\""), ("If-Modified-Since", same)]
+        ),
+        None
+    );
+
+    assert_eq!(evaluate(Method::Put, &[("If-Match", "\"v1\"")]), None);
+    assert_eq!(evaluate(Method::Put, &[("If-Match", "*")]), None);
+    assert_eq!(evaluate(Method::Put, &[("If-Match", "W/\"v1\"")]), failed);
+    assert_eq!(evaluate(Method::Put, &[("If-Match", "\"v2\"")]), failed);
+
+    assert_eq!(
+        evaluate(Method::Put, &[("If-Unmodified-Since", same)]),
+        None
+    );
+    assert_eq!(
+        evaluate(Method::Put, &[("If-Unmodified-Since", before)]),
+        failed
+    );
+    // If-Match takes precedence
+    assert_eq!(
+        evaluate(
+            Method::Put,
+            &[("If-Match", "\"v1\""), ("If-Unmodified-Since", before)]
+        ),
+        None
+    );
+
+    // Without validators there is no current representation, so nothing matches, not even `*`
+    let missing = |headers: [(&str, &str); 1]| {
+        let mut req = range_request(headers);
+        req.method = Method::Put;
+        Preconditions::from_request(&req).evaluate(None, None)
+    };
+    assert_eq!(missing([("If-Match", "\"v1\"")]), failed);
+    assert_eq!(missing([("If-Match", "*")]), failed);
+    assert_eq!(missing([("If-None-Match", "*")]), None);
+    assert_eq!(missing([("If-None-Match", "\"v1\"")]), None);
+}
+
+#[test]
+fn test_check_preconditions() {
+    let etag = ETag::strong("v1");
+    let check = |method: Method, headers: [(&str, &str); 1]| {
+        let mut req = range_request(headers);
+        req.method = method;
+        req.check_preconditions(Some(&etag), None)
+            .map_err(|e| e.status)
+    };
+
+    assert_eq!(check(Method::Put, [("If-Match", "\"v1\"")]), Ok(()));
+    assert_eq!(
+        check(Method::Put, [("If-Match", "\"v2\"")]),
+        Err(StatusCode::PreconditionFailed)
+    );
+    assert_eq!(
+        check(Method::Put, [("If-None-Match", "*")]),
+        Err(StatusCode::PreconditionFailed)
+    );
+    // Left to the response, a 304 error would get a body
+    assert_eq!(check(Method::Get, [("If-None-Match", "\"v1\"")]), Ok(()));
+    assert_eq!(
+        check(Method::Get, [("If-Match", "\"v2\"")]),
+        Err(StatusCode::PreconditionFailed)
+    );
+
+    // Creating a resource only if it doesn't exist yet
+    let mut req = range_request([("If-None-Match", "*")]);
+    req.method = Method::Put;
+    assert_eq!(
+        req.check_preconditions(None, None).map_err(|e| e.status),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_conditional_response() {
+    let response = || {
+        HttpResponse::builder()
+            .text("hello".to_owned())
+            .etag(&ETag::weak("v1"))
+            .header("Cache-Control", "no-cache".to_owned())
+            .build()
+    };
+
+    let req = range_request([("If-None-Match", "W/\"v1\"")]);
+    let not_modified = response().conditional(&req);
+    assert_eq!(not_modified.status, StatusCode::NotModified);
+    assert_eq!(not_modified.body, None);
+    assert_eq!(
+        not_modified.headers,
+        HeaderMap::from([("ETag", "W/\"v1\""), ("Cache-Control", "no-cache")])
+    );
+
+    let req = range_request([("If-Match", "W/\"v1\"")]);
+    let failed = response().conditional(&req);
+    assert_eq!(failed.status, StatusCode::PreconditionFailed);
+    assert_eq!(failed.body, None);
+    assert_eq!(failed.headers.get("Content-Type"), None);
+
+    // Errors are never replaced
+    let error = (StatusCode::NotFound, response()).into_response();
+    assert_eq!(error.conditional(&req).status, StatusCode::NotFound);
+}
+
+#[test]
+fn test_negotiate_encoding() {
+    let gzip = Some(ContentCoding::Gzip);
+    let deflate = Some(ContentCoding::Deflate);
+
+    assert_eq!(negotiate_encoding("gzip, deflate, br"), gzip);
+    assert_eq!(negotiate_encoding("deflate, gzip"), gzip);
+    assert_eq!(negotiate_encoding("deflate"), deflate);
+    assert_eq!(negotiate_encoding("GZIP"), gzip);
+    assert_eq!(negotiate_encoding("x-gzip"), gzip);
+    assert_eq!(negotiate_encoding("gzip;q=0.5, deflate;q=0.8"), deflate);
+    assert_eq!(negotiate_encoding("gzip; q=1.0, deflate;q=0.8"), gzip);
+    assert_eq!(negotiate_encoding("*"), gzip);
+    assert_eq!(negotiate_encoding("gzip;q=0, *"), deflate);
+    assert_eq!(negotiate_encoding("*;q=0, deflate"), deflate);
+
+    for none in [
+        "", "identity", "br", "gzip;q=0", "*;q=0", "gzip;q=2", "gzip;q=x",
+    ] {
+        assert_eq!(negotiate_encoding(none), None, "{none}");
+    }
+}
+
+#[test]
+fn test_compress_response() {
+    let html = "<p>compress me</p>".repeat(100);
+    let response = || {
+        HttpResponse::builder()
+            .body(Some(html.clone().into_bytes()))
+            .header("Content-Type", "text/html".to_owned())
+            .etag(&ETag::strong("v1"))
+            .header("Accept-Ranges", "bytes".to_owned())
+            .build()
+    };
+
+    let compressed = response().compress(Some("gzip, deflate"));
+    assert_eq!(compressed.headers.get("Content-Encoding").unwrap(), "gzip");
+    assert_eq!(compressed.headers.get("Vary").unwrap(), "Accept-Encoding");
+    assert_eq!(compressed.headers.get("ETag").unwrap(), "W/\"v1\"");
+    assert_eq!(compressed.headers.get("Accept-Ranges"), None);
+    assert_eq!(
+        compressed.body,
+        Some(compress(html.as_bytes(), Format::Gzip))
+    );
+
+    let compressed = response().compress(Some("deflate"));
+    assert_eq!(
+        compressed.headers.get("Content-Encoding").unwrap(),
+        "deflate"
+    );
+    assert_eq!(
+        compressed.body,
+        Some(compress(html.as_bytes(), Format::Zlib))
+    );
+
+    // Not accepted, but caches still need to know about it
+    let plain = response().compress(None);
+    assert_eq!(plain.headers.get("Content-Encoding"), None);
+    assert_eq!(plain.headers.get("Vary").unwrap(), "Accept-Encoding");
+    assert_eq!(plain.body, Some(html.clone().into_bytes()));
+
+    let mut with_vary = response();
+    with_vary
+        .headers
+        .values
+        .insert("Vary".to_owned(), "Cookie".to_owned());
+    let compressed = with_vary.compress(Some("gzip"));
+    assert_eq!(
+        compressed.headers.get("Vary").unwrap(),
+        "Cookie, Accept-Encoding"
+    );
+    let compressed = compressed.compress(Some("gzip"));
+    assert_eq!(
+        compressed.headers.get("Vary").unwrap(),
+        "Cookie, Accept-Encoding"
+    );
+
+    // A 304 has no body, but has to vary like the response it stands in for
+    let not_modified = HttpResponse::builder()
+        .status(StatusCode::NotModified)
+        .etag(&ETag::strong("v1"))
+        .build()
+        .compress(Some("gzip"));
+    assert_eq!(not_modified.headers.get("Vary").unwrap(), "Accept-Encoding");
+    assert_eq!(not_modified.body, None);
+}
+
+#[test]
+fn test_compress_skipped() {
+    let big = vec![b'a'; 4096];
+    let skipped = |response: HttpResponse| {
+        let body = response.body.clone();
+        response.compress(Some("gzip")).body == body
+    };
+
+    assert!(!skipped(HttpResponse::builder().bytes(big.clone()).build()));
+    assert!(skipped(
+        HttpResponse::builder().bytes(vec![b'a'; 100]).build()
+    ));
+    assert!(skipped(
+        HttpResponse::builder()
+            .bytes(big.clone())
+            .header("Content-Type", "image/png".to_owned())
+            .build()
+    ));
+    assert!(skipped(
+        HttpResponse::builder()
+            .bytes(big.clone())
+            .header("Content-Type", "application/zip".to_owned())
+            .build()
+    ));
+    assert!(skipped(
+        HttpResponse::builder()
+            .bytes(big.clone())
+            .header("Content-Encoding", "br".to_owned())
+            .build()
+    ));
+    assert!(skipped(
+        HttpResponse::builder()
+            .bytes(big.clone())
+            .status(StatusCode::PartialContent)
+            .build()
+    ));
+    assert!(skipped(HttpResponse::builder().build()));
+
+    // Incompressible data is sent as is
+    let mut state = 0x2545_f491_u32;
+    let noise = (0..4096)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect::<Vec<_>>();
+    let response = HttpResponse::builder().bytes(noise.clone()).build();
+    assert_eq!(response.compress(Some("gzip")).body, Some(noise));
+}
+
+#[test]
+fn test_compress_stream() {
+    let text = "streamed text ".repeat(10_000);
+    let response = HttpResponse::builder()
+        .stream(BodyStream::new(
+            Cursor::new(text.clone().into_bytes()),
+            Some(text.len() as u64),
+        ))
+        .header("Content-Type", "text/plain".to_owned())
+        .build()
+        .compress(Some("gzip"));
+
+    assert_eq!(response.headers.get("Content-Encoding").unwrap(), "gzip");
+    let stream = response.stream.clone().unwrap();
+    assert_eq!(stream.content_length(), None);
+
+    let mut out = Vec::new();
+    write_response(&mut out, response).unwrap();
+    let head_end = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(out[..head_end].to_vec()).unwrap();
+    assert!(head.contains("Transfer-Encoding: chunked"));
+    assert!(!head.contains("Content-Length"));
+
+    // Reassemble the chunks
+    let mut chunks = &out[head_end + 4..];
+    let mut gzip = Vec::new();
+    loop {
+        let line_end = chunks.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size =
+            usize::from_str_radix(std::str::from_utf8(&chunks[..line_end]).unwrap(), 16).unwrap();
+        chunks = &chunks[line_end + 2..];
+        if size == 0 {
+            break;
+        }
+        gzip.extend_from_slice(&chunks[..size]);
+        chunks = &chunks[size + 2..];
+    }
+
+    assert_eq!(gzip[..3], [0x1f, 0x8b, 8]);
+    assert!(gzip.len() < text.len() / 10);
+    let trailer = &gzip[gzip.len() - 4..];
+    assert_eq!(trailer, (text.len() as u32).to_le_bytes());
 }
@@ -0,0 +1,43 @@
+use std::fmt::Display;
+
+/// Why a JSON document was rejected, and at which byte offset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    pub kind: JsonErrorKind,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonErrorKind {
+    UnexpectedEnd,
+    UnexpectedChar(char),
+    TrailingCharacters,
+    InvalidNumber,
+    InvalidEscape,
+    InvalidUnicodeEscape,
+    ControlCharacterInString,
+    TooDeep { max: usize },
+    TooLarge { max: usize },
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            JsonErrorKind::UnexpectedEnd => write!(f, "Unexpected end of input"),
+            JsonErrorKind::UnexpectedChar(c) => write!(f, "Unexpected character {c:?}"),
+            JsonErrorKind::TrailingCharacters => write!(f, "Trailing characters after the value"),
+            JsonErrorKind::InvalidNumber => write!(f, "Invalid number"),
+            JsonErrorKind::InvalidEscape => write!(f, "Invalid escape sequence"),
+            JsonErrorKind::InvalidUnicodeEscape => write!(f, "Invalid unicode escape"),
+            JsonErrorKind::ControlCharacterInString => {
+                write!(f, "Unescaped control character in string")
+            }
+            JsonErrorKind::TooDeep { max } => write!(f, "Nesting deeper than {max} levels"),
+            JsonErrorKind::TooLarge { max } => write!(f, "Document larger than {max} bytes"),
+        }?;
+
+        write!(f, " at offset {}", self.offset)
+    }
+}
+
+impl std::error::Error for JsonError {}
@@ -0,0 +1,215 @@
+mod error;
+mod parse;
+mod serialize;
+
+#[cfg(test)]
+mod test;
+
+use crate::extract::{FromJson, ToJson};
+use std::ops::Index;
+
+pub use error::{JsonError, JsonErrorKind};
+pub use parse::Limits;
+
+/// A JSON value (RFC 8259)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum JsonValue {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// Members in document order, duplicate keys are kept
+    Object(Vec<(String, JsonValue)>),
+}
+
+/// Returned when indexing into something that isn't there
+static NULL: JsonValue = JsonValue::Null;
+
+impl JsonValue {
+    /// Parses a complete JSON document with the default [`Limits`]
+    pub fn parse(json: &str) -> Result<Self, JsonError> {
+        Self::parse_with_limits(json, Limits::default())
+    }
+
+    pub fn parse_with_limits(json: &str, limits: Limits) -> Result<Self, JsonError> {
+        parse::parse(json, limits)
+    }
+
+    /// Serializes without any whitespace
+    pub fn to_compact_string(&self) -> String {
+        let mut out = String::new();
+        serialize::write_value(&mut out, self, None, 0);
+        out
+    }
+
+    /// Serializes with newlines and two spaces of indentation per level
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        serialize::write_value(&mut out, self, Some("  "), 0);
+        out
+    }
+
+    /// Looks up an object member, the last one wins if the key is duplicated
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members
+                .iter()
+                .rev()
+                .find_map(|(k, v)| (k == key).then_some(v)),
+            _ => None,
+        }
+    }
+
+    /// Sets an object member, replacing the existing one. Does nothing if this isn't an object.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<JsonValue>) {
+        let JsonValue::Object(members) = self else {
+            return;
+        };
+
+        let key = key.into();
+        let value = value.into();
+        match members.iter_mut().rev().find(|(k, _)| *k == key) {
+            Some((_, existing)) => *existing = value,
+            None => members.push((key, value)),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, JsonValue::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The number as an integer, if it has no fractional part and fits
+    pub fn as_i64(&self) -> Option<i64> {
+        let n = self.as_f64()?;
+        (n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64).then_some(n as i64)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&Vec<(String, JsonValue)>> {
+        match self {
+            JsonValue::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+/// `value["key"]` is [`JsonValue::Null`] for missing keys and non-objects
+impl Index<&str> for JsonValue {
+    type Output = JsonValue;
+
+    fn index(&self, key: &str) -> &Self::Output {
+        self.get(key).unwrap_or(&NULL)
+    }
+}
+
+/// `value[0]` is [`JsonValue::Null`] when out of bounds and for non-arrays
+impl Index<usize> for JsonValue {
+    type Output = JsonValue;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.as_array()
+            .and_then(|items| items.get(index))
+            .unwrap_or(&NULL)
+    }
+}
+
+impl std::fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_compact_string())
+    }
+}
+
+impl FromJson for JsonValue {
+    fn from_json(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(JsonValue::parse(json)?)
+    }
+}
+
+impl ToJson for JsonValue {
+    fn to_json(&self) -> String {
+        self.to_compact_string()
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        JsonValue::Bool(value)
+    }
+}
+
+macro_rules! impl_from_number {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for JsonValue {
+                fn from(value: $ty) -> Self {
+                    JsonValue::Number(value as f64)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_number!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        JsonValue::String(value)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        JsonValue::String(value.to_owned())
+    }
+}
+
+impl<T: Into<JsonValue>> From<Vec<T>> for JsonValue {
+    fn from(value: Vec<T>) -> Self {
+        JsonValue::Array(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<JsonValue>> From<Option<T>> for JsonValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(JsonValue::Null, Into::into)
+    }
+}
+
+impl<K: Into<String>, V: Into<JsonValue>> FromIterator<(K, V)> for JsonValue {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        JsonValue::Object(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,327 @@
+use super::{JsonError, JsonErrorKind, JsonValue};
+
+/// Bounds on what the parser accepts, so untrusted documents can't exhaust memory or the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// How deeply arrays and objects may be nested
+    pub max_depth: usize,
+    /// The maximum document size in bytes
+    pub max_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 128,
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+pub(super) fn parse(json: &str, limits: Limits) -> Result<JsonValue, JsonError> {
+    if json.len() > limits.max_size {
+        return Err(JsonError {
+            kind: JsonErrorKind::TooLarge {
+                max: limits.max_size,
+            },
+            offset: limits.max_size,
+        });
+    }
+
+    let mut parser = Parser {
+        input: json,
+        pos: 0,
+        depth: 0,
+        max_depth: limits.max_depth,
+    };
+
+    parser.skip_whitespace();
+    let value = parser.value()?;
+    parser.skip_whitespace();
+
+    if parser.pos != json.len() {
+        return Err(parser.error(JsonErrorKind::TrailingCharacters));
+    }
+
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, kind: JsonErrorKind) -> JsonError {
+        JsonError {
+            kind,
+            offset: self.pos,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    /// Reports the (possibly multi byte) character at the current position
+    fn unexpected(&self) -> JsonError {
+        match self.input[self.pos..].chars().next() {
+            Some(c) => self.error(JsonErrorKind::UnexpectedChar(c)),
+            None => self.error(JsonErrorKind::UnexpectedEnd),
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        if self.peek() != Some(byte) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Only the four whitespace characters of RFC 8259 are allowed
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, JsonError> {
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => self.string().map(JsonValue::String),
+            Some(b'-' | b'0'..=b'9') => self.number().map(JsonValue::Number),
+            Some(b't') => self.literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.literal("null", JsonValue::Null),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<JsonValue, JsonError>,
+    ) -> Result<JsonValue, JsonError> {
+        if self.depth == self.max_depth {
+            return Err(self.error(JsonErrorKind::TooDeep {
+                max: self.max_depth,
+            }));
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        for expected in literal.bytes() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect(b'[')?;
+        self.skip_whitespace();
+
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(JsonValue::Array(items));
+        }
+
+        loop {
+            self.skip_whitespace();
+            items.push(self.value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect(b'{')?;
+        self.skip_whitespace();
+
+        let mut members = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(JsonValue::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.unexpected());
+            }
+            let key = self.string()?;
+
+            self.skip_whitespace();
+            self.expect(b':')?;
+            self.skip_whitespace();
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+
+        let mut out = String::new();
+        loop {
+            // Copy everything up to the next quote, escape or control character at once
+            let start = self.pos;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            out.push_str(&self.input[start..self.pos]);
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    out.push(self.escape()?);
+                }
+                Some(_) => return Err(self.error(JsonErrorKind::ControlCharacterInString)),
+                None => return Err(self.error(JsonErrorKind::UnexpectedEnd)),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let escaped = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                return self.unicode_escape();
+            }
+            Some(_) => return Err(self.error(JsonErrorKind::InvalidEscape)),
+            None => return Err(self.error(JsonErrorKind::UnexpectedEnd)),
+        };
+
+        self.pos += 1;
+        Ok(escaped)
+    }
+
+    /// Decodes `XXXX` after a `\u`, combining UTF-16 surrogate pairs
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let start = self.pos;
+        let high = self.hex4()?;
+
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if !self.input[self.pos..].starts_with("\\u") {
+                    return Err(self.error(JsonErrorKind::InvalidUnicodeEscape));
+                }
+                self.pos += 2;
+
+                let low = self.hex4()?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(self.error(JsonErrorKind::InvalidUnicodeEscape));
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            }
+            0xDC00..=0xDFFF => {
+                self.pos = start;
+                return Err(self.error(JsonErrorKind::InvalidUnicodeEscape));
+            }
+            code => code,
+        };
+
+        char::from_u32(code).ok_or_else(|| self.error(JsonErrorKind::InvalidUnicodeEscape))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error(JsonErrorKind::InvalidUnicodeEscape))?;
+
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).expect("checked to be hex digits"))
+    }
+
+    fn number(&mut self) -> Result<f64, JsonError> {
+        let start = self.pos;
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+
+        // No leading zeros: either a single 0 or a non-zero digit followed by digits
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.error(JsonErrorKind::InvalidNumber)),
+        }
+
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.required_digits()?;
+        }
+
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            self.required_digits()?;
+        }
+
+        match self.input[start..self.pos].parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(number),
+            _ => {
+                self.pos = start;
+                Err(self.error(JsonErrorKind::InvalidNumber))
+            }
+        }
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn required_digits(&mut self) -> Result<(), JsonError> {
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return Err(self.error(JsonErrorKind::InvalidNumber));
+        }
+        self.digits();
+        Ok(())
+    }
+}
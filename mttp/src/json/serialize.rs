@@ -0,0 +1,104 @@
+use super::JsonValue;
+use std::fmt::Write;
+
+/// Writes `value`, pretty printed with `indent` per level if it is set
+pub(super) fn write_value(out: &mut String, value: &JsonValue, indent: Option<&str>, level: usize) {
+    match value {
+        JsonValue::Null => out.push_str("null"),
+        JsonValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        JsonValue::Number(n) => write_number(out, *n),
+        JsonValue::String(s) => write_string(out, s),
+        JsonValue::Array(items) => {
+            write_container(out, ('[', ']'), items, indent, level, |out, item| {
+                write_value(out, item, indent, level + 1)
+            })
+        }
+        JsonValue::Object(members) => write_container(
+            out,
+            ('{', '}'),
+            members,
+            indent,
+            level,
+            |out, (key, value)| {
+                write_string(out, key);
+                out.push(':');
+                if indent.is_some() {
+                    out.push(' ');
+                }
+                write_value(out, value, indent, level + 1);
+            },
+        ),
+    }
+}
+
+fn write_container<T>(
+    out: &mut String,
+    (open, close): (char, char),
+    items: &[T],
+    indent: Option<&str>,
+    level: usize,
+    mut write_item: impl FnMut(&mut String, &T),
+) {
+    out.push(open);
+
+    // Empty containers stay on one line even when pretty printing
+    if items.is_empty() {
+        out.push(close);
+        return;
+    }
+
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        newline(out, indent, level + 1);
+        write_item(out, item);
+    }
+
+    newline(out, indent, level);
+    out.push(close);
+}
+
+fn newline(out: &mut String, indent: Option<&str>, level: usize) {
+    if let Some(indent) = indent {
+        out.push('\n');
+        for _ in 0..level {
+            out.push_str(indent);
+        }
+    }
+}
+
+fn write_number(out: &mut String, n: f64) {
+    // JSON has no representation for these
+    if !n.is_finite() {
+        out.push_str("null");
+        return;
+    }
+
+    // Integers are written without a trailing `.0` as long as they are exact
+    if n.fract() == 0.0 && n.abs() < (1u64 << 53) as f64 {
+        write!(out, "{}", n as i64).expect("writing to a String can't fail");
+    } else {
+        write!(out, "{n}").expect("writing to a String can't fail");
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", c as u32).expect("writing to a String can't fail")
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,156 @@
+use super::{JsonErrorKind, JsonValue, Limits};
+use crate::http::{HttpRequest, HttpResponse, StatusCode};
+
+fn kind(json: &str) -> JsonErrorKind {
+    JsonValue::parse(json).unwrap_err().kind
+}
+
+#[test]
+fn test_parse_values() {
+    assert_eq!(JsonValue::parse("null"), Ok(JsonValue::Null));
+    assert_eq!(JsonValue::parse(" true "), Ok(JsonValue::Bool(true)));
+    assert_eq!(JsonValue::parse("-12.5e2"), Ok(JsonValue::Number(-1250.0)));
+    assert_eq!(JsonValue::parse("0"), Ok(JsonValue::Number(0.0)));
+    assert_eq!(
+        JsonValue::parse(r#""a\"b\\c\/\né😀""#),
+        Ok(JsonValue::String("a\"b\\c/\né😀".to_owned()))
+    );
+
+    let value = JsonValue::parse(r#"{"a": [1, {"b": null}], "c": "d", "a": 2}"#).unwrap();
+    assert_eq!(value["a"], JsonValue::Number(2.0));
+    assert_eq!(value["c"].as_str(), Some("d"));
+    assert_eq!(value["missing"][3], JsonValue::Null);
+    assert_eq!(value.as_object().unwrap().len(), 3);
+}
+
+#[test]
+fn test_parse_rejects_invalid() {
+    assert_eq!(kind(""), JsonErrorKind::UnexpectedEnd);
+    assert_eq!(kind("[1,]"), JsonErrorKind::UnexpectedChar(']'));
+    assert_eq!(kind("{\"a\":1,}"), JsonErrorKind::UnexpectedChar('}'));
+    assert_eq!(kind("{a:1}"), JsonErrorKind::UnexpectedChar('a'));
+    assert_eq!(kind("01"), JsonErrorKind::TrailingCharacters);
+    assert_eq!(kind("1."), JsonErrorKind::InvalidNumber);
+    assert_eq!(kind("-"), JsonErrorKind::InvalidNumber);
+    assert_eq!(kind("+1"), JsonErrorKind::UnexpectedChar('+'));
+    assert_eq!(kind("1e999"), JsonErrorKind::InvalidNumber);
+    assert_eq!(kind("\"\t\""), JsonErrorKind::ControlCharacterInString);
+    assert_eq!(kind(r#""\x""#), JsonErrorKind::InvalidEscape);
+    assert_eq!(kind(r#""\ud83d""#), JsonErrorKind::InvalidUnicodeEscape);
+    assert_eq!(kind(r#""\ude00""#), JsonErrorKind::InvalidUnicodeEscape);
+    assert_eq!(kind("\"abc"), JsonErrorKind::UnexpectedEnd);
+    assert_eq!(kind("nul"), JsonErrorKind::UnexpectedEnd);
+    assert_eq!(kind("[1] [2]"), JsonErrorKind::TrailingCharacters);
+    assert_eq!(kind("\u{a0}1"), JsonErrorKind::UnexpectedChar('\u{a0}'));
+
+    let error = JsonValue::parse("[1, x]").unwrap_err();
+    assert_eq!(error.offset, 4);
+    assert_eq!(error.to_string(), "Unexpected character 'x' at offset 4");
+}
+
+#[test]
+fn test_limits() {
+    let limits = Limits {
+        max_depth: 3,
+        max_size: 16,
+    };
+
+    assert!(JsonValue::parse_with_limits("[[[1]]]", limits).is_ok());
+    assert_eq!(
+        JsonValue::parse_with_limits("[[[[1]]]]", limits)
+            .unwrap_err()
+            .kind,
+        JsonErrorKind::TooDeep { max: 3 }
+    );
+    assert_eq!(
+        JsonValue::parse_with_limits("\"0123456789abcdef\"", limits)
+            .unwrap_err()
+            .kind,
+        JsonErrorKind::TooLarge { max: 16 }
+    );
+
+    // deep nesting must fail cleanly instead of overflowing the stack
+    let deep = "[".repeat(100_000);
+    assert_eq!(kind(&deep), JsonErrorKind::TooDeep { max: 128 });
+}
+
+#[test]
+fn test_serialize() {
+    let value: JsonValue = [
+        ("name", JsonValue::from("a \"quoted\"\n\u{1}")),
+        ("count", JsonValue::from(3)),
+        ("ratio", JsonValue::from(0.5)),
+        ("tags", JsonValue::from(vec!["x", "y"])),
+        ("empty", JsonValue::Array(Vec::new())),
+        ("none", JsonValue::from(None::<bool>)),
+    ]
+    .into_iter()
+    .collect();
+
+    assert_eq!(
+        value.to_compact_string(),
+        r#"{"name":"a \"quoted\"\n\u0001","count":3,"ratio":0.5,"tags":["x","y"],"empty":[],"none":null}"#
+    );
+
+    assert_eq!(
+        JsonValue::parse(r#"{"a":[1,{"b":true}],"c":{}}"#)
+            .unwrap()
+            .to_pretty_string(),
+        "{\n  \"a\": [\n    1,\n    {\n      \"b\": true\n    }\n  ],\n  \"c\": {}\n}"
+    );
+
+    assert_eq!(JsonValue::Number(f64::NAN).to_compact_string(), "null");
+    assert_eq!(JsonValue::Number(1e300).as_i64(), None);
+}
+
+#[test]
+fn test_round_trip() {
+    for json in [
+        r#"{"a":[1,-2.5,1e-7,true,false,null],"b":{"c":"\u001f\\"}}"#,
+        r#"["é😀","\"",[],{}]"#,
+        "9007199254740991",
+    ] {
+        let value = JsonValue::parse(json).unwrap();
+        assert_eq!(
+            JsonValue::parse(&value.to_compact_string()),
+            Ok(value.clone())
+        );
+        assert_eq!(JsonValue::parse(&value.to_pretty_string()), Ok(value));
+    }
+}
+
+#[test]
+fn test_insert() {
+    let mut value = JsonValue::Object(Vec::new());
+    value.insert("a", 1);
+    value.insert("b", "x");
+    value.insert("a", 2);
+
+    assert_eq!(value.to_compact_string(), r#"{"a":2,"b":"x"}"#);
+}
+
+#[test]
+fn test_request_and_response() {
+    let mut req = HttpRequest {
+        body: Some(br#"{"id": 5}"#.to_vec()),
+        ..Default::default()
+    };
+    assert_eq!(
+        req.json().unwrap_err().status,
+        StatusCode::UnsupportedMediaType
+    );
+
+    req.headers
+        .values
+        .insert("Content-Type".to_owned(), "application/json".to_owned());
+    assert_eq!(req.json().unwrap()["id"].as_i64(), Some(5));
+
+    req.body = Some(b"{".to_vec());
+    assert_eq!(req.json().unwrap_err().status, StatusCode::BadRequest);
+
+    let response = HttpResponse::builder()
+        .json_value(&[("ok", true)].into_iter().collect())
+        .build();
+    assert_eq!(response.body.as_deref(), Some(br#"{"ok":true}"#.as_ref()));
+    assert_eq!(response.headers.content_type(), Some("application/json"));
+}
@@ -9,4 +9,18 @@ pub mod websocket;
 
 /// Contains the main server implementation
 pub mod server;
-mod url;
+
+/// Contains the extractors handlers can take as arguments
+pub mod extract;
+
+/// Contains a JSON parser and serializer
+pub mod json;
+
+/// Contains the connection abstraction the server runs on
+pub mod transport;
+
+/// Contains URL parsing, building and percent-encoding
+pub mod url;
+
+/// Contains a DEFLATE compressor with gzip and zlib framing
+pub mod compress;
@@ -1,5 +1,8 @@
-use super::{HttpHandlerFunc, HttpResult, RegisteredRoute};
-use crate::http::{self, HttpRequest, HttpResponse, Method};
+use super::{handler, Handler, HttpResult, RegisteredRoute};
+use crate::{
+    http::{HttpError, HttpRequest, HttpResponse, Method},
+    transport::Transport,
+};
 use std::{collections::HashMap, sync::Arc};
 
 pub fn not_found<Hs>(_: Arc<Hs>, _: HttpRequest) -> HttpResult {
@@ -13,19 +16,31 @@ pub fn method_not_allowed<Hs>(_: Arc<Hs>, _: HttpRequest) -> HttpResult {
         .build())
 }
 
-pub fn error(e: Box<dyn std::error::Error>) -> HttpResponse {
-    println!("Error returned from handler: {e}");
+pub fn error(req: &HttpRequest, e: Box<dyn std::error::Error>) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<HttpError>() {
+        return e.response();
+    }
+
+    println!(
+        "Error returned from handler for {} {}: {e}",
+        req.method, req.route
+    );
     HttpResponse::builder()
         .status(crate::http::StatusCode::InternalServerError)
         .text("Oops, something went wrong".to_owned())
         .build()
 }
 
-pub fn make_default<S: Clone>(handler: HttpHandlerFunc<S>) -> RegisteredRoute<S> {
+pub fn make_default<State: 'static, T: Transport, Args>(
+    handler: impl Handler<State, Args>,
+) -> RegisteredRoute<Arc<State>, T> {
     RegisteredRoute {
-        handler: super::HandlerType::Http(handler),
+        handler: super::HandlerType::Http(handler::boxed(handler)),
         specific_middlewares: Vec::new(),
         method: Method::Get,
         params: HashMap::new(),
+        pattern: None,
+        stream_body: false,
+        listener: None,
     }
 }
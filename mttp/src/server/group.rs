@@ -0,0 +1,111 @@
+use super::{
+    handler,
+    middleware::Middleware,
+    public_funcs::{impl_method_func, impl_websocket_func},
+    AroundFunc, Handler, HandlerType, MiddlewareFunc, RegisteredRoute, WsHandlerFunc,
+};
+use crate::{
+    http::Method,
+    transport::{Address, Transport},
+};
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+};
+
+/// A set of routes sharing a path prefix and middlewares.
+///
+/// Groups can be built in place with [`Server::group`](super::Server::group), or created with
+/// [`RouteGroup::new`] (for example in another module) and mounted with [`Server::mount`](super::Server::mount).
+/// Groups can be nested, prefixes are concatenated and middlewares stack from the outermost
+/// group inwards, followed by the middlewares of the route itself.
+#[derive(Debug)]
+pub struct RouteGroup<State: 'static + Send + Sync, T: Transport = TcpStream> {
+    routes: Vec<(String, RegisteredRoute<Arc<State>, T>)>,
+    middlewares: Vec<Middleware<Arc<State>>>,
+    listener: Option<Address>,
+}
+
+impl<State: 'static + Send + Sync, T: Transport> Default for RouteGroup<State, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<State: 'static + Send + Sync, T: Transport> RouteGroup<State, T> {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            middlewares: Vec::new(),
+            listener: None,
+        }
+    }
+
+    impl_method_func!(post, Post);
+    impl_method_func!(get, Get);
+    impl_method_func!(put, Put);
+    impl_method_func!(patch, Patch);
+    impl_method_func!(delete, Delete);
+
+    impl_websocket_func!();
+
+    fn add_route(&mut self, route: &str, handler: RegisteredRoute<Arc<State>, T>) {
+        self.routes.push((route.to_owned(), handler));
+    }
+
+    /// Adds a middleware that runs for every route in this group (including nested groups),
+    /// no matter if the routes were added before or after it
+    pub fn middleware(&mut self, handler: MiddlewareFunc<Arc<State>>) {
+        self.middlewares.push(Middleware::Before(handler));
+    }
+
+    /// Adds an around middleware for every route in this group,
+    /// see [`Server::around`](super::Server::around)
+    pub fn around(&mut self, handler: AroundFunc<Arc<State>>) {
+        self.middlewares.push(Middleware::Around(handler));
+    }
+
+    /// Only serve the routes of this group on the listener bound to `addr`,
+    /// see [`Server::only_on`](super::Server::only_on)
+    pub fn only_on(&mut self, addr: SocketAddr) {
+        self.listener = Some(Address::Tcp(addr));
+    }
+
+    /// Adds a nested group built in place
+    pub fn group(&mut self, prefix: &str, build: impl FnOnce(&mut RouteGroup<State, T>)) {
+        let mut group = RouteGroup::new();
+        build(&mut group);
+        self.mount(prefix, group);
+    }
+
+    /// Adds all routes of `group` with their paths prefixed by `prefix`
+    pub fn mount(&mut self, prefix: &str, group: RouteGroup<State, T>) {
+        self.routes.extend(group.into_routes(prefix));
+    }
+
+    /// Resolves the final paths and middlewares of all routes in this group
+    pub(super) fn into_routes(
+        self,
+        prefix: &str,
+    ) -> impl Iterator<Item = (String, RegisteredRoute<Arc<State>, T>)> {
+        let prefix = prefix.trim_end_matches('/').to_owned();
+
+        self.routes.into_iter().map(move |(route, mut handler)| {
+            let mut middlewares = self.middlewares.clone();
+            middlewares.append(&mut handler.specific_middlewares);
+            handler.specific_middlewares = middlewares;
+
+            // an inner restriction is more specific than one on the outer group
+            handler.listener = handler.listener.or(self.listener);
+
+            let route = match route.trim_start_matches('/') {
+                "" if prefix.is_empty() => "/".to_owned(),
+                "" => prefix.clone(),
+                route => format!("{prefix}/{route}"),
+            };
+
+            (route, handler)
+        })
+    }
+}
@@ -0,0 +1,69 @@
+use super::{HttpHandlerFunc, HttpResult};
+use crate::{
+    extract::FromRequest,
+    http::{HttpRequest, IntoResponse},
+};
+use std::{error::Error, sync::Arc};
+
+/// A function that can handle HTTP requests.
+///
+/// Implemented for functions and closures taking up to 8 [extractors](crate::extract)
+/// and returning anything that implements [`IntoHttpResult`], like
+/// `fn(Arc<State>, HttpRequest) -> HttpResult` or `fn(Path<u64>, Query<HashMap<String, String>>) -> String`.
+/// `Args` only exists to tell apart the implementations for different arities.
+pub trait Handler<State, Args>: Send + Sync + 'static {
+    fn handle(&self, state: Arc<State>, req: HttpRequest) -> HttpResult;
+}
+
+/// The return value of a [`Handler`].
+///
+/// Implemented for every [`IntoResponse`], and for results whose error is a `Box<dyn Error>`.
+/// Those errors are passed to the server's error handler.
+pub trait IntoHttpResult {
+    fn into_http_result(self) -> HttpResult;
+}
+
+impl<T: IntoResponse> IntoHttpResult for T {
+    fn into_http_result(self) -> HttpResult {
+        Ok(self.into_response())
+    }
+}
+
+impl<T: IntoResponse> IntoHttpResult for Result<T, Box<dyn Error>> {
+    fn into_http_result(self) -> HttpResult {
+        self.map(IntoResponse::into_response)
+    }
+}
+
+macro_rules! impl_handler {
+    ($($ty:ident $arg:ident),*) => {
+        impl<State, F, R, $($ty,)*> Handler<State, ($($ty,)*)> for F
+        where
+            F: Fn($($ty),*) -> R + Send + Sync + 'static,
+            R: IntoHttpResult,
+            $($ty: FromRequest<State>,)*
+        {
+            #[allow(unused_variables, unused_mut)]
+            fn handle(&self, state: Arc<State>, mut req: HttpRequest) -> HttpResult {
+                $(let $arg = $ty::from_request(&mut req, &state)?;)*
+                self($($arg),*).into_http_result()
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(A a);
+impl_handler!(A a, B b);
+impl_handler!(A a, B b, C c);
+impl_handler!(A a, B b, C c, D d);
+impl_handler!(A a, B b, C c, D d, E e);
+impl_handler!(A a, B b, C c, D d, E e, G g);
+impl_handler!(A a, B b, C c, D d, E e, G g, H h);
+impl_handler!(A a, B b, C c, D d, E e, G g, H h, I i);
+
+pub(super) fn boxed<State: 'static, Args>(
+    handler: impl Handler<State, Args>,
+) -> HttpHandlerFunc<Arc<State>> {
+    Arc::new(move |state, req| handler.handle(state, req))
+}
@@ -0,0 +1,101 @@
+use super::{HttpResult, MiddlewareFunc, MiddlewareResult};
+use crate::http::{
+    consts::headers::{ACCEPT_ENCODING, ETAG},
+    ETag, HttpRequest, Method, Preconditions, StatusCode,
+};
+
+/// A middleware wrapping everything after it, see [`Server::around`](super::Server::around)
+pub type AroundFunc<S> = fn(S, HttpRequest, Next<'_, S>) -> HttpResult;
+
+#[derive(Debug)]
+pub(crate) enum Middleware<S> {
+    /// Runs before the handler and can only abort the request
+    Before(MiddlewareFunc<S>),
+    Around(AroundFunc<S>),
+}
+
+impl<S> Clone for Middleware<S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> Copy for Middleware<S> {}
+
+/// The rest of the middleware chain, ending in the route handler.
+///
+/// Passed to around middlewares, which decide if, when and with which request it runs.
+pub struct Next<'a, S> {
+    state: S,
+    chain: &'a [Middleware<S>],
+    handler: &'a dyn Fn(S, HttpRequest) -> HttpResult,
+}
+
+impl<'a, S: Clone> Next<'a, S> {
+    pub(crate) fn new(
+        state: S,
+        chain: &'a [Middleware<S>],
+        handler: &'a dyn Fn(S, HttpRequest) -> HttpResult,
+    ) -> Self {
+        Self {
+            state,
+            chain,
+            handler,
+        }
+    }
+
+    /// Runs the remaining middlewares and the handler, returning their response
+    pub fn run(self, mut req: HttpRequest) -> HttpResult {
+        let Some((middleware, rest)) = self.chain.split_first() else {
+            return (self.handler)(self.state, req);
+        };
+
+        let next = Next::new(self.state.clone(), rest, self.handler);
+        match middleware {
+            Middleware::Before(middleware) => match middleware(self.state, &mut req) {
+                MiddlewareResult::Continue => next.run(req),
+                MiddlewareResult::Abort(abort) => Ok(abort),
+            },
+            Middleware::Around(middleware) => middleware(self.state, req, next),
+        }
+    }
+}
+
+/// An around middleware answering conditional GET requests.
+///
+/// Successful responses with a body get a strong `ETag` computed from it, unless the handler
+/// already set one. Then `If-Match`, `If-None-Match`, `If-Modified-Since` and
+/// `If-Unmodified-Since` are evaluated against the response's `ETag` and `Last-Modified`,
+/// replacing it with an empty 304 or 412 where needed.
+///
+/// Other methods pass through unchanged: the preconditions are only checked after the handler
+/// ran, too late for a request that modifies state. Their handlers check them up front with
+/// [`HttpRequest::check_preconditions`] instead.
+pub fn conditional_requests<S: Clone>(_: S, req: HttpRequest, next: Next<'_, S>) -> HttpResult {
+    if req.method != Method::Get {
+        return next.run(req);
+    }
+
+    let preconditions = Preconditions::from_request(&req);
+    let mut response = next.run(req)?;
+
+    if response.status == StatusCode::Ok && !response.headers.values.contains_key(ETAG) {
+        if let Some(body) = &response.body {
+            let etag = ETag::from_body(body).to_string();
+            response.headers.values.insert(ETAG.to_owned(), etag);
+        }
+    }
+
+    Ok(preconditions.apply(response))
+}
+
+/// An around middleware compressing responses with gzip or deflate, as negotiated through
+/// the request's `Accept-Encoding`, see [`HttpResponse::compress`](crate::http::HttpResponse::compress).
+///
+/// Add it before [`conditional_requests`], so the `ETag` is checked against the uncompressed body.
+pub fn compression<S: Clone>(_: S, req: HttpRequest, next: Next<'_, S>) -> HttpResult {
+    let accept_encoding = req.headers.get(ACCEPT_ENCODING).cloned();
+    let response = next.run(req)?;
+
+    Ok(response.compress(accept_encoding.as_deref()))
+}
@@ -1,53 +1,122 @@
 use crate::{
     http::{
-        protocol::{parse_request, write_response},
+        forwarded,
+        protocol::{parse_request_head, read_request_body, write_response},
         request::HttpRequest,
         response::HttpResponse,
         Method, StatusCode,
     },
+    transport::{Address, Listener, Transport},
+    url::EncodedSlashes,
     websocket::{self, WsConnection},
 };
-use routing::{build_dynamic_routes, router};
+use middleware::Middleware;
+use routing::{router, Router};
 use std::{
-    collections::HashMap,
-    net::{SocketAddr, TcpListener},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    io,
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
     sync::{atomic::AtomicI64, Arc},
     time::Instant,
 };
 
 mod default_handlers;
+mod group;
+mod handler;
+mod middleware;
 mod public_funcs;
 mod routing;
+mod static_files;
+#[cfg(unix)]
+mod systemd;
+#[cfg(unix)]
+mod unix;
+
+pub use group::RouteGroup;
+pub use handler::{Handler, IntoHttpResult};
+pub use middleware::{compression, conditional_requests, AroundFunc, Next};
+pub use routing::RouteError;
+pub use static_files::{HiddenFiles, StaticFiles, Symlinks};
+#[cfg(unix)]
+pub use systemd::systemd_listeners;
+
+#[cfg(test)]
+mod test;
 
 #[derive(Debug)]
-pub struct Server<State: 'static + Send + Sync> {
+pub struct Server<State: 'static + Send + Sync, T: Transport = TcpStream> {
     state: Arc<State>,
-    not_found_handler: RegisteredRoute<Arc<State>>,
-    method_not_allowd_handler: RegisteredRoute<Arc<State>>,
-    error_handler: fn(error: Box<dyn std::error::Error>) -> HttpResponse,
-    handlers: Handlers<State>,
+    not_found_handler: RegisteredRoute<Arc<State>, T>,
+    method_not_allowd_handler: RegisteredRoute<Arc<State>, T>,
+    error_handler: ErrorHandlerFunc,
+    router: Router<State, T>,
     thread_counter: Arc<AtomicI64>,
-    middlewares: Vec<MiddlewareFunc<Arc<State>>>,
+    middlewares: Vec<Middleware<Arc<State>>>,
     inspector: fn(&HttpResponse),
+    unix_socket_mode: Option<u32>,
+    trusted_proxies: Arc<Vec<IpAddr>>,
+    trust_unix_peers: bool,
+    encoded_slashes: EncodedSlashes,
 }
 
-type Handlers<State> = HashMap<String, RegisteredRoute<Arc<State>>>;
-type HttpHandlerFunc<S> = fn(S, HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>>;
+type HttpHandlerFunc<S> = Arc<dyn Fn(S, HttpRequest) -> HttpResult + Send + Sync>;
 type MiddlewareFunc<S> = fn(S, &mut HttpRequest) -> MiddlewareResult;
-pub(crate) type WsHandlerFunc<S> = fn(S, &HttpRequest, WsConnection);
+/// Receives the failed request without its body, which was handed to the handler
+type ErrorHandlerFunc = fn(&HttpRequest, Box<dyn std::error::Error>) -> HttpResponse;
+pub(crate) type WsHandlerFunc<S, T> = fn(S, &HttpRequest, WsConnection<T>);
 
-#[derive(Debug, Clone)]
-enum HandlerType<S> {
-    WebSocket(WsHandlerFunc<S>),
+enum HandlerType<S, T: Transport> {
+    WebSocket(WsHandlerFunc<S, T>),
     Http(HttpHandlerFunc<S>),
 }
 
-#[derive(Debug, Clone)]
-struct RegisteredRoute<S: Clone> {
-    handler: HandlerType<S>,
-    specific_middlewares: Vec<MiddlewareFunc<S>>,
+// Derived `Clone` would require the transport itself to be `Clone`
+impl<S, T: Transport> Clone for HandlerType<S, T> {
+    fn clone(&self) -> Self {
+        match self {
+            HandlerType::WebSocket(handler) => HandlerType::WebSocket(*handler),
+            HandlerType::Http(handler) => HandlerType::Http(handler.clone()),
+        }
+    }
+}
+
+impl<S, T: Transport> Debug for HandlerType<S, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandlerType::WebSocket(handler) => f.debug_tuple("WebSocket").field(handler).finish(),
+            HandlerType::Http(_) => f.write_str("Http"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RegisteredRoute<S: Clone, T: Transport> {
+    handler: HandlerType<S, T>,
+    specific_middlewares: Vec<Middleware<S>>,
     method: Method,
     params: HashMap<String, String>,
+    /// The pattern this route was registered with, filled in when it is looked up
+    pattern: Option<String>,
+    /// Only serve this route on the listener bound to this address
+    listener: Option<Address>,
+    /// Leave the body on the connection for the handler to stream, see [`Server::stream_body`]
+    stream_body: bool,
+}
+
+impl<S: Clone, T: Transport> Clone for RegisteredRoute<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            specific_middlewares: self.specific_middlewares.clone(),
+            method: self.method,
+            params: self.params.clone(),
+            pattern: self.pattern.clone(),
+            listener: self.listener,
+            stream_body: self.stream_body,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -59,20 +128,96 @@ pub enum MiddlewareResult {
 pub type HttpResult = Result<HttpResponse, Box<dyn std::error::Error>>;
 
 impl<State: 'static + Send + Sync> Server<State> {
-    pub fn start(self, addr: SocketAddr) -> std::io::Result<()> {
-        println!("Binding mttp server to http://{}", addr);
-        let socket = TcpListener::bind(addr)?;
+    /// Binds a TCP listener to every address `addrs` resolves to and starts serving requests on all of them
+    ///
+    /// Several addresses can be passed as a slice, e.g. `[ipv4_addr, ipv6_addr].as_slice()`
+    pub fn start(self, addrs: impl ToSocketAddrs) -> io::Result<()> {
+        let addrs = unique(addrs.to_socket_addrs()?);
 
-        let dynamic_routes = Arc::new(build_dynamic_routes(self.handlers));
+        let listeners = addrs
+            .into_iter()
+            .map(|addr| {
+                println!("Binding mttp server to http://{}", addr);
+                TcpListener::bind(addr)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        self.serve_all(listeners)
+    }
+
+    /// Starts serving requests on an already bound listener
+    pub fn start_with_listener(self, listener: TcpListener) -> io::Result<()> {
+        self.start_with_listeners(vec![listener])
+    }
+
+    /// Starts serving requests on several already bound listeners
+    pub fn start_with_listeners(self, listeners: Vec<TcpListener>) -> io::Result<()> {
+        for listener in &listeners {
+            println!("Serving mttp on http://{}", listener.local_addr()?);
+        }
+        self.serve_all(listeners)
+    }
+}
+
+impl<State: 'static + Send + Sync, T: Transport> Server<State, T> {
+    /// Serves requests on connections accepted from any [`Listener`]
+    pub fn serve<L: Listener<Stream = T> + Send>(self, listener: L) -> io::Result<()> {
+        self.serve_all(vec![listener])
+    }
+
+    /// Serves requests on several listeners at once, each one gets its own accept loop.
+    ///
+    /// All listeners share the same routes and state.
+    /// Returns once every accept loop has stopped.
+    pub fn serve_all<L: Listener<Stream = T> + Send>(
+        mut self,
+        listeners: Vec<L>,
+    ) -> io::Result<()> {
+        if listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no listeners to serve on",
+            ));
+        }
+
+        let dynamic_routes = Arc::new(std::mem::take(&mut self.router));
         println!("[mttp] {} routes registered", dynamic_routes.len());
 
-        while let Ok((mut stream, addr)) = socket.accept() {
+        let server = &self;
+        let dynamic_routes = &dynamic_routes;
+        let results = std::thread::scope(|scope| {
+            listeners
+                .into_iter()
+                .map(|listener| scope.spawn(move || server.accept_loop(listener, dynamic_routes)))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|accept_loop| accept_loop.join().expect("accept loop panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        println!("Stopping server");
+        results.into_iter().collect()
+    }
+
+    fn accept_loop<L: Listener<Stream = T>>(
+        &self,
+        listener: L,
+        dynamic_routes: &Arc<Router<State, T>>,
+    ) -> io::Result<()> {
+        let local_addr = listener.local_addr()?;
+
+        while let Ok(mut stream) = listener.accept() {
+            let addr = stream.peer_addr().unwrap_or(Address::Unknown);
             let state = self.state.clone();
             let not_found_handler = self.not_found_handler.clone();
             let method_not_allowed_handler = self.method_not_allowd_handler.clone();
             let dynamic_routes = dynamic_routes.clone();
             let middlewares = self.middlewares.clone();
             let error_handler = self.error_handler;
+            let inspector = self.inspector;
+            let trusted_proxies = self.trusted_proxies.clone();
+            let trust_unix_peers = self.trust_unix_peers;
+            let encoded_slashes = self.encoded_slashes;
 
             let start = Instant::now();
 
@@ -82,67 +227,118 @@ impl<State: 'static + Send + Sync> Server<State> {
             std::thread::Builder::new()
                 .name(format!("mttp worker thread #{thread_id} for {}", addr))
                 .spawn(move || {
-                    let parsed_request = parse_request(&mut stream);
-                    let handler_attempt = match parsed_request {
-                        Ok(mut parsed_request) => {
+                    let parsed_request = parse_request_head(&mut stream, encoded_slashes);
+                    let final_response = match parsed_request {
+                        Ok(mut parsed_request) => 'response: {
+                            parsed_request.peer_addr = addr;
+                            parsed_request.local_addr = local_addr;
+                            parsed_request.client_ip = forwarded::client_ip(
+                                addr,
+                                &parsed_request.headers,
+                                &trusted_proxies,
+                                trust_unix_peers,
+                            );
+
                             let handler = router(
                                 &dynamic_routes,
                                 not_found_handler,
                                 method_not_allowed_handler,
                                 &parsed_request,
+                                local_addr,
                             );
 
+                            if let Err(e) = read_request_body(
+                                &mut parsed_request,
+                                &mut stream,
+                                handler.stream_body,
+                            ) {
+                                break 'response bad_request(e);
+                            }
+
                             let mut middlewares = middlewares;
                             middlewares.extend(handler.specific_middlewares);
 
-                            parsed_request.params.extend(handler.params);
+                            parsed_request.path_params = handler.params;
+                            parsed_request.matched_route = handler.pattern;
 
-                            if let Some(abort) = middlewares
-                                .into_iter()
-                                .map(|middleware| middleware(state.clone(), &mut parsed_request))
-                                .find_map(|x| match x {
-                                    MiddlewareResult::Continue => None,
-                                    MiddlewareResult::Abort(abort) => Some(abort),
-                                })
-                            {
-                                Ok(abort)
-                            } else {
-                                match handler.handler {
-                                    HandlerType::WebSocket(handler) => {
-                                        println!("{:.2?}", start.elapsed());
-                                        let ws_connection =
-                                            websocket::websocket_handshake(&parsed_request, stream)
-                                                .expect("Failed websocket handshake");
-
-                                        // WS Handler gets run here
-                                        (handler)(state.clone(), &parsed_request, ws_connection);
-
-                                        return;
-                                    }
-                                    HandlerType::Http(handler) => {
-                                        // HTTP handler gets run here
-                                        (handler)(state.clone(), parsed_request)
+                            let context = RefCell::new(without_body(&mut parsed_request));
+                            // Websocket routes run through the middlewares as well, the end of the
+                            // chain hands the request back for the handshake instead of handling it
+                            let upgrade = RefCell::new(None);
+
+                            let terminal = |state: Arc<State>, mut req: HttpRequest| {
+                                // Middlewares may have changed the request, the error handler
+                                // should see it like the handler did
+                                *context.borrow_mut() = without_body(&mut req);
+
+                                match &handler.handler {
+                                    // HTTP handler gets run here
+                                    HandlerType::Http(handler) => handler(state, req),
+                                    HandlerType::WebSocket(_) => {
+                                        *upgrade.borrow_mut() = Some(req);
+                                        Ok(HttpResponse::builder()
+                                            .status(StatusCode::SwitchingProtocols)
+                                            .build())
                                     }
                                 }
+                            };
+
+                            let result = Next::new(state.clone(), &middlewares, &terminal)
+                                .run(parsed_request);
+
+                            if let (HandlerType::WebSocket(handler), Some(parsed_request)) =
+                                (handler.handler, upgrade.into_inner())
+                            {
+                                println!("{:.2?}", start.elapsed());
+                                let ws_connection =
+                                    websocket::websocket_handshake(&parsed_request, stream)
+                                        .expect("Failed websocket handshake");
+
+                                // WS Handler gets run here
+                                (handler)(state.clone(), &parsed_request, ws_connection);
+
+                                return;
                             }
-                        }
-                        Err(e) => Ok(HttpResponse::builder()
-                            .status(StatusCode::BadRequest)
-                            .text(format!("Error processing HTTP: {}", e))
-                            .build()),
-                    };
 
-                    let final_response = match handler_attempt {
-                        Ok(v) => v,
-                        Err(e) => error_handler(e),
+                            match result {
+                                Ok(response) => response,
+                                Err(e) => error_handler(&context.borrow(), e),
+                            }
+                        }
+                        Err(e) => bad_request(e),
                     };
 
-                    (self.inspector)(&final_response);
+                    inspector(&final_response);
                     write_response(&mut stream, final_response).expect("Failed to write response");
                 })?;
         }
 
-        println!("Stopping server");
         Ok(())
     }
 }
+
+/// The addresses without duplicates, in the order they were first seen
+fn unique(addrs: impl IntoIterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let mut seen = HashSet::new();
+    addrs
+        .into_iter()
+        .filter(|addr| seen.insert(*addr))
+        .collect()
+}
+
+fn bad_request(e: crate::http::Error) -> HttpResponse {
+    HttpResponse::builder()
+        .status(StatusCode::BadRequest)
+        .text(format!("Error processing HTTP: {}", e))
+        .build()
+}
+
+/// Clones everything but the body, which stays with the request
+fn without_body(req: &mut HttpRequest) -> HttpRequest {
+    let body = req.body.take();
+    let body_stream = req.body_stream.take();
+    let context = req.clone();
+    req.body = body;
+    req.body_stream = body_stream;
+    context
+}
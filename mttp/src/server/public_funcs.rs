@@ -1,41 +1,102 @@
 use super::{
     default_handlers::{self, make_default},
-    HandlerType, HttpHandlerFunc, MiddlewareFunc, RegisteredRoute, Server, WsHandlerFunc,
+    handler,
+    middleware::Middleware,
+    routing::Router,
+    AroundFunc, ErrorHandlerFunc, Handler, HandlerType, MiddlewareFunc, RegisteredRoute,
+    RouteError, RouteGroup, Server, StaticFiles, WsHandlerFunc,
+};
+use crate::{
+    http::{HttpResponse, Method},
+    transport::{Address, Transport},
+    url::EncodedSlashes,
 };
-use crate::http::{HttpResponse, Method};
 use std::{
     collections::HashMap,
+    net::{IpAddr, SocketAddr},
     sync::{atomic::AtomicI64, Arc},
 };
 
 macro_rules! impl_method_func {
     ($name:ident, $method:ident) => {
-        pub fn $name(
+        /// Registers a route for this method
+        ///
+        /// # Panics
+        /// If the route pattern is invalid or conflicts with an already registered route.
+        /// For route groups this happens once the group is mounted.
+        pub fn $name<Args>(
             &mut self,
             route: &str,
-            handler: HttpHandlerFunc<Arc<State>>,
+            handler: impl Handler<State, Args>,
             middleware: Vec<MiddlewareFunc<Arc<State>>>,
         ) {
-            self.handlers.insert(
-                route.to_owned(),
+            self.add_route(
+                route,
                 RegisteredRoute {
-                    handler: HandlerType::Http(handler),
+                    handler: HandlerType::Http(handler::boxed(handler)),
                     method: Method::$method,
                     params: HashMap::new(),
-                    specific_middlewares: middleware,
+                    pattern: None,
+                    stream_body: false,
+                    listener: None,
+                    specific_middlewares: middleware.into_iter().map(Middleware::Before).collect(),
                 },
             );
         }
     };
 }
 
+macro_rules! impl_websocket_func {
+    () => {
+        /// Registers a websocket route, panics like the HTTP method functions
+        pub fn websocket(
+            &mut self,
+            route: &str,
+            handler: WsHandlerFunc<Arc<State>, T>,
+            middleware: Vec<MiddlewareFunc<Arc<State>>>,
+        ) {
+            self.add_route(
+                route,
+                RegisteredRoute {
+                    handler: HandlerType::WebSocket(handler),
+                    specific_middlewares: middleware.into_iter().map(Middleware::Before).collect(),
+                    method: Method::Get,
+                    params: HashMap::new(),
+                    pattern: None,
+                    stream_body: false,
+                    listener: None,
+                },
+            );
+        }
+    };
+}
+
+pub(super) use {impl_method_func, impl_websocket_func};
+
+macro_rules! impl_try_method_func {
+    ($name:ident, $method:ident) => {
+        /// Registers a route for this method, see [`Server::try_route`]
+        pub fn $name<Args>(
+            &mut self,
+            route: &str,
+            handler: impl Handler<State, Args>,
+            middleware: Vec<MiddlewareFunc<Arc<State>>>,
+        ) -> Result<(), RouteError> {
+            self.try_route(Method::$method, route, handler, middleware)
+        }
+    };
+}
+
 macro_rules! impl_specific_handler_func {
     ($name:ident) => {
-        pub fn $name(&mut self, handler: HttpHandlerFunc<Arc<State>>) {
+        pub fn $name<Args>(&mut self, handler: impl Handler<State, Args>) {
             self.$name = RegisteredRoute {
-                handler: HandlerType::Http(handler),
+                handler: HandlerType::Http(handler::boxed(handler)),
                 method: Method::Get,
                 params: HashMap::new(),
+                pattern: None,
+                stream_body: false,
+                listener: None,
                 specific_middlewares: Vec::new(),
             };
         }
@@ -44,8 +105,15 @@ macro_rules! impl_specific_handler_func {
 
 impl<State: 'static + Send + Sync> Server<State> {
     pub fn new(state: State) -> Self {
+        Self::with_transport(state)
+    }
+}
+
+impl<State: 'static + Send + Sync, T: Transport> Server<State, T> {
+    /// Creates a server for connections of a custom [`Transport`]
+    pub fn with_transport(state: State) -> Self {
         Self {
-            handlers: HashMap::new(),
+            router: Router::default(),
             state: Arc::new(state),
             not_found_handler: make_default(default_handlers::not_found),
             method_not_allowd_handler: make_default(default_handlers::method_not_allowed),
@@ -53,10 +121,20 @@ impl<State: 'static + Send + Sync> Server<State> {
             middlewares: Vec::new(),
             inspector: |_| {},
             error_handler: default_handlers::error,
+            unix_socket_mode: None,
+            trusted_proxies: Arc::new(Vec::new()),
+            trust_unix_peers: true,
+            encoded_slashes: EncodedSlashes::default(),
         }
     }
 
-    pub fn error_handler(&mut self, handler: fn(Box<dyn std::error::Error>) -> HttpResponse) {
+    /// Sets the function turning errors returned from handlers into responses.
+    ///
+    /// It gets the request that failed (without its body), so it can for example
+    /// render errors as JSON or HTML depending on the `Accept` header.
+    /// The pattern of the route that failed is in `matched_route`.
+    /// [`HttpError`](crate::http::HttpError)s can be recovered with `downcast_ref`.
+    pub fn error_handler(&mut self, handler: ErrorHandlerFunc) {
         self.error_handler = handler
     }
 
@@ -66,28 +144,144 @@ impl<State: 'static + Send + Sync> Server<State> {
     impl_method_func!(patch, Patch);
     impl_method_func!(delete, Delete);
 
-    impl_specific_handler_func!(not_found_handler);
-    impl_specific_handler_func!(method_not_allowd_handler);
+    impl_try_method_func!(try_post, Post);
+    impl_try_method_func!(try_get, Get);
+    impl_try_method_func!(try_put, Put);
+    impl_try_method_func!(try_patch, Patch);
+    impl_try_method_func!(try_delete, Delete);
 
-    pub fn websocket(
+    /// Registers a route, returning an error instead of panicking
+    /// if the pattern is invalid or conflicts with an already registered route.
+    ///
+    /// Nothing is registered if it fails.
+    pub fn try_route<Args>(
         &mut self,
+        method: Method,
         route: &str,
-        handler: WsHandlerFunc<Arc<State>>,
+        handler: impl Handler<State, Args>,
         middleware: Vec<MiddlewareFunc<Arc<State>>>,
-    ) {
-        self.handlers.insert(
-            route.to_owned(),
+    ) -> Result<(), RouteError> {
+        self.router.insert(
+            route,
             RegisteredRoute {
-                handler: HandlerType::WebSocket(handler),
-                specific_middlewares: middleware,
-                method: Method::Get,
+                handler: HandlerType::Http(handler::boxed(handler)),
+                method,
                 params: HashMap::new(),
+                pattern: None,
+                stream_body: false,
+                listener: None,
+                specific_middlewares: middleware.into_iter().map(Middleware::Before).collect(),
             },
-        );
+        )
+    }
+
+    impl_specific_handler_func!(not_found_handler);
+    impl_specific_handler_func!(method_not_allowd_handler);
+
+    impl_websocket_func!();
+
+    fn add_route(&mut self, route: &str, handler: RegisteredRoute<Arc<State>, T>) {
+        self.router
+            .insert(route, handler)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Registers the routes of a group built in place, with all paths prefixed by `prefix`
+    pub fn group(&mut self, prefix: &str, build: impl FnOnce(&mut RouteGroup<State, T>)) {
+        let mut group = RouteGroup::new();
+        build(&mut group);
+        self.mount(prefix, group);
+    }
+
+    /// Registers all routes of `group` with their paths prefixed by `prefix`
+    ///
+    /// # Panics
+    /// If any of the routes is invalid or conflicts with an already registered route.
+    /// Nothing of the group is registered in that case.
+    pub fn mount(&mut self, prefix: &str, group: RouteGroup<State, T>) {
+        self.try_mount(prefix, group)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Like [`mount`](Self::mount), but returns an error instead of panicking.
+    ///
+    /// Either all routes of the group are registered or none are.
+    pub fn try_mount(
+        &mut self,
+        prefix: &str,
+        group: RouteGroup<State, T>,
+    ) -> Result<(), RouteError> {
+        self.router.insert_all(group.into_routes(prefix).collect())
+    }
+
+    /// Serves the files of `files` below `prefix`, e.g. `/assets/app.js` from `web/app.js`
+    /// for `server.static_files("/assets", StaticFiles::new("web"))`
+    pub fn static_files(&mut self, prefix: &str, files: StaticFiles) {
+        let route = format!("{}/*", prefix.trim_end_matches('/'));
+        self.get(&route, files, vec![]);
+    }
+
+    /// Restricts an already registered route to the listener bound to `addr`
+    ///
+    /// Requests for it arriving on any other listener are treated as if the route didn't exist.
+    /// Useful for admin endpoints that should only be reachable on an internal port.
+    /// To serve the same path with different handlers on different listeners,
+    /// register them in groups restricted with [`RouteGroup::only_on`].
+    pub fn only_on(&mut self, route: &str, addr: SocketAddr) {
+        let restricted = self.router.for_each_mut(route, |handler| {
+            handler.listener = Some(Address::Tcp(addr));
+        });
+
+        if restricted == 0 {
+            panic!("Cannot restrict route {route}: it has not been registered");
+        }
+    }
+
+    /// Leaves the body of requests to an already registered route on the connection,
+    /// instead of reading it into memory before the route's middlewares and handler run.
+    ///
+    /// The handler reads it with [`HttpRequest::take_body_stream`](crate::http::HttpRequest::take_body_stream)
+    /// or [`HttpRequest::multipart`](crate::http::HttpRequest::multipart), so large uploads
+    /// never have to fit into memory. [`body`](crate::http::HttpRequest::body) stays `None`.
+    pub fn stream_body(&mut self, route: &str) {
+        let changed = self.router.for_each_mut(route, |handler| {
+            handler.stream_body = true;
+        });
+
+        if changed == 0 {
+            panic!("Cannot stream the body of route {route}: it has not been registered");
+        }
+    }
+
+    /// Sets the proxies whose `Forwarded` / `X-Forwarded-For` headers are believed
+    /// when resolving [`HttpRequest::client_ip`](crate::http::HttpRequest::client_ip)
+    pub fn trusted_proxies(&mut self, proxies: Vec<IpAddr>) {
+        self.trusted_proxies = Arc::new(proxies);
+    }
+
+    /// Sets whether peers connected over a unix socket count as trusted proxies (the default).
+    ///
+    /// Turn this off if untrusted local processes can connect to the socket.
+    pub fn trust_unix_peers(&mut self, trust: bool) {
+        self.trust_unix_peers = trust;
+    }
+
+    /// Sets whether a `%2F` in a request path is rejected (the default) or kept inside its segment
+    pub fn encoded_slashes(&mut self, encoded_slashes: EncodedSlashes) {
+        self.encoded_slashes = encoded_slashes;
     }
 
     pub fn middleware(&mut self, handler: MiddlewareFunc<Arc<State>>) {
-        self.middlewares.push(handler);
+        self.middlewares.push(Middleware::Before(handler));
+    }
+
+    /// Adds a middleware that wraps all later middlewares and the handler.
+    ///
+    /// It decides whether to call [`Next::run`](super::Next::run) and can change the response
+    /// (or error) it returns, e.g. to add headers or measure timing.
+    /// Global middlewares run in the order they were added, before route specific ones.
+    pub fn around(&mut self, handler: AroundFunc<Arc<State>>) {
+        self.middlewares.push(Middleware::Around(handler));
     }
 
     pub fn inspector(&mut self, inspector: fn(&HttpResponse)) {
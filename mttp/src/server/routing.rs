@@ -1,132 +1,513 @@
-use super::{Handlers, RegisteredRoute};
-use crate::http::HttpRequest;
-use std::{
-    collections::HashMap,
-    ffi::OsString,
-    fmt::Debug,
-    path::{Path, PathBuf},
-    sync::Arc,
+use super::RegisteredRoute;
+use crate::{
+    http::{HttpRequest, Method},
+    transport::{Address, Transport},
+    url::decode_segment,
 };
+use std::{collections::HashMap, fmt::Display, sync::Arc};
 
-#[derive(Debug, Clone)]
-pub enum Route<State> {
-    Static {
-        route: PathBuf,
-        handler: RegisteredRoute<Arc<State>>,
-    },
-    Dynamic {
-        route: PathBuf,
-        dynamic_component_positions: HashMap<usize, OsString>,
-        handler: RegisteredRoute<Arc<State>>,
+/// Prefix tree over the `/` separated segments of all registered routes.
+///
+/// Lookups walk the tree one request segment at a time, so they don't get slower
+/// with the number of routes. When several routes could match, static segments
+/// take precedence over `:params`, which take precedence over `*catch-alls`,
+/// independent of registration order.
+///
+/// If a static branch turns out not to match further down, the lookup backtracks
+/// and tries the `:param` branch. Every node is visited at most once per lookup though,
+/// so even then the work is bounded by the number of nodes matching a prefix of the path,
+/// at most the number of routes times the path length. It only comes close to that
+/// for route sets mixing static segments and params at the same positions over many levels.
+///
+/// Supported segments:
+/// - `name`: matches exactly `name`
+/// - `:name`: matches any single segment, captured as `name`
+/// - `:name?`: like `:name`, but the segment may also be left out
+/// - `*name`: only allowed at the end, matches all remaining segments (even none), captured as `name`.
+///   A bare `*` or `**` is captured as `*`
+///
+/// Registering two routes that match exactly the same paths with the same method
+/// (on the same listener) is an error, so the route a request ends up at never depends
+/// on registration order.
+#[derive(Debug)]
+pub struct Router<State, T: Transport> {
+    root: Node<State, T>,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct Node<State, T: Transport> {
+    statics: HashMap<String, Node<State, T>>,
+    param: Option<Box<Node<State, T>>>,
+    endpoints: Vec<Endpoint<State, T>>,
+    /// Routes ending in a catch-all right after this node
+    catch_all: Vec<Endpoint<State, T>>,
+}
+
+/// A route registered on a node, one per method
+#[derive(Debug)]
+struct Endpoint<State, T: Transport> {
+    /// The pattern the route was registered with
+    pattern: String,
+    /// Names of the `:params` (and the catch-all) in the pattern, in order
+    param_names: Vec<String>,
+    handler: RegisteredRoute<Arc<State>, T>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Segment<'a> {
+    Static(&'a str),
+    Param { name: &'a str, optional: bool },
+    CatchAll(&'a str),
+}
+
+/// Problems with a route pattern, detected when the route is registered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// The pattern contains `//`
+    EmptySegment { route: String },
+    /// A `:` without a name following it
+    MissingParamName { route: String },
+    /// The same param name is used twice
+    DuplicateParam { route: String, name: String },
+    /// A `*catch-all` that isn't the last segment
+    CatchAllNotLast { route: String },
+    /// Another route already matches exactly the same paths with the same method on the same listener
+    Conflict {
+        route: String,
+        existing: String,
+        method: Method,
     },
 }
 
-pub fn build_dynamic_routes<State>(handlers: Handlers<State>) -> Vec<Route<State>> {
-    handlers
-        .into_iter()
-        .map(|(route, handler)| {
-            let path = Path::new(&route);
-
-            let positions = path
-                .components()
-                .enumerate()
-                .filter_map(|(pos, component)| {
-                    let string = component.as_os_str().to_string_lossy();
-                    if string.starts_with(':') {
-                        Some((pos, component.as_os_str().to_owned()))
-                    } else {
-                        None
-                    }
-                })
-                .collect::<HashMap<_, _>>();
+impl Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::EmptySegment { route } => {
+                write!(f, "Invalid route {route}: contains an empty segment")
+            }
+            RouteError::MissingParamName { route } => {
+                write!(f, "Invalid route {route}: a parameter is missing its name")
+            }
+            RouteError::DuplicateParam { route, name } => {
+                write!(f, "Invalid route {route}: parameter {name} is used twice")
+            }
+            RouteError::CatchAllNotLast { route } => {
+                write!(
+                    f,
+                    "Invalid route {route}: a catch-all must be the last segment"
+                )
+            }
+            RouteError::Conflict {
+                route,
+                existing,
+                method,
+            } => write!(
+                f,
+                "{method} route {route} conflicts with already registered route {existing}"
+            ),
+        }
+    }
+}
 
-            if positions.is_empty() {
-                Route::Static {
-                    route: PathBuf::from(route),
-                    handler,
-                }
-            } else {
-                Route::Dynamic {
-                    dynamic_component_positions: positions,
-                    route: PathBuf::from(route),
-                    handler,
-                }
+impl std::error::Error for RouteError {}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment<'_>>, RouteError> {
+    let route = || pattern.to_owned();
+
+    let mut raw = pattern.split('/').collect::<Vec<_>>();
+    // a leading and a trailing slash are fine, `//` anywhere else isn't
+    if raw.first() == Some(&"") {
+        raw.remove(0);
+    }
+    if raw.last() == Some(&"") {
+        raw.pop();
+    }
+
+    let mut names = Vec::new();
+    let mut segments = Vec::with_capacity(raw.len());
+
+    for segment in raw {
+        let segment = if segment.is_empty() {
+            return Err(RouteError::EmptySegment { route: route() });
+        } else if let Some(name) = segment.strip_prefix(':') {
+            let (name, optional) = match name.strip_suffix('?') {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+
+            if name.is_empty() {
+                return Err(RouteError::MissingParamName { route: route() });
             }
+
+            Segment::Param { name, optional }
+        } else if let Some(name) = segment.strip_prefix('*') {
+            match name {
+                "" | "*" => Segment::CatchAll("*"),
+                name => Segment::CatchAll(name),
+            }
+        } else {
+            Segment::Static(segment)
+        };
+
+        if let Segment::Param { name, .. } | Segment::CatchAll(name) = segment {
+            if names.contains(&name) {
+                return Err(RouteError::DuplicateParam {
+                    route: route(),
+                    name: name.to_owned(),
+                });
+            }
+            names.push(name);
+        }
+
+        if let Some(Segment::CatchAll(_)) = segments.last() {
+            return Err(RouteError::CatchAllNotLast { route: route() });
+        }
+
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+/// Two variants with the same shape match exactly the same paths
+fn same_shape(a: &[Segment], b: &[Segment]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|pair| match pair {
+            (Segment::Static(a), Segment::Static(b)) => a == b,
+            (Segment::Param { .. }, Segment::Param { .. }) => true,
+            (Segment::CatchAll(_), Segment::CatchAll(_)) => true,
+            _ => false,
         })
-        .collect()
 }
 
-pub fn router<State: 'static + Send + Sync>(
-    routes: &[Route<State>],
-    not_found_handler: RegisteredRoute<Arc<State>>,
-    method_not_allowed_handler: RegisteredRoute<Arc<State>>,
-    current_request: &HttpRequest,
-) -> RegisteredRoute<Arc<State>> {
-    if let Some(handler) = match_route(routes, current_request) {
-        if current_request.method == handler.method {
-            handler.clone()
-        } else {
-            method_not_allowed_handler.clone()
+/// Two routes of the same shape only conflict if a request could reach both,
+/// so not if they are for different methods or restricted to different listeners
+fn overlapping<S: Clone, T: Transport>(
+    a: &RegisteredRoute<S, T>,
+    b: &RegisteredRoute<S, T>,
+) -> bool {
+    a.method == b.method
+        && match (a.listener, b.listener) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+}
+
+/// Expands optional params into every combination of leaving them out or requiring them
+fn expand_optional<'a>(segments: &[Segment<'a>]) -> Vec<Vec<Segment<'a>>> {
+    segments
+        .iter()
+        .fold(vec![Vec::new()], |variants, segment| match *segment {
+            Segment::Param {
+                name,
+                optional: true,
+            } => variants
+                .into_iter()
+                .flat_map(|variant| {
+                    let mut with = variant.clone();
+                    with.push(Segment::Param {
+                        name,
+                        optional: false,
+                    });
+                    [variant, with]
+                })
+                .collect(),
+            segment => variants
+                .into_iter()
+                .map(|mut variant| {
+                    variant.push(segment);
+                    variant
+                })
+                .collect(),
+        })
+}
+
+impl<State, T: Transport> Default for Node<State, T> {
+    fn default() -> Self {
+        Self {
+            statics: HashMap::new(),
+            param: None,
+            endpoints: Vec::new(),
+            catch_all: Vec::new(),
         }
-    } else {
-        not_found_handler.clone()
     }
 }
 
-fn match_route<State>(
-    routes: &[Route<State>],
-    request: &HttpRequest,
-) -> Option<RegisteredRoute<Arc<State>>> {
-    let req_route = Path::new(&request.route);
+impl<State, T: Transport> Default for Router<State, T> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+            len: 0,
+        }
+    }
+}
+
+impl<State, T: Transport> Router<State, T> {
+    /// The number of registered routes, routes with optional segments count once for every variant
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Registers a route.
+    ///
+    /// Fails without registering anything if the pattern is invalid or the route
+    /// would conflict with an already registered one.
+    pub fn insert(
+        &mut self,
+        pattern: &str,
+        handler: RegisteredRoute<Arc<State>, T>,
+    ) -> Result<(), RouteError> {
+        self.insert_all(vec![(pattern.to_owned(), handler)])
+    }
+
+    /// Registers several routes at once.
+    ///
+    /// Either all of them are registered, or none if any is invalid or conflicts
+    /// with an already registered route or another one of `routes`.
+    pub fn insert_all(
+        &mut self,
+        routes: Vec<(String, RegisteredRoute<Arc<State>, T>)>,
+    ) -> Result<(), RouteError> {
+        let mut pending = Vec::<(&str, Vec<Segment>, &_)>::new();
+
+        for (pattern, handler) in &routes {
+            for variant in expand_optional(&parse_pattern(pattern)?) {
+                let existing = pending
+                    .iter()
+                    .find(|(_, other, other_handler)| {
+                        same_shape(&variant, other) && overlapping(handler, other_handler)
+                    })
+                    .map(|(other, ..)| *other)
+                    .or_else(|| {
+                        self.root
+                            .get(&variant)
+                            .and_then(|node| {
+                                node.endpoints_for(&variant)
+                                    .iter()
+                                    .find(|endpoint| overlapping(&endpoint.handler, handler))
+                            })
+                            .map(|endpoint| endpoint.pattern.as_str())
+                    });
 
-    routes.iter().find_map(|route| match route {
-        Route::Static { route, handler } => {
-            if req_route == route {
-                Some(handler.clone())
-            } else {
-                None
+                if let Some(existing) = existing {
+                    return Err(RouteError::Conflict {
+                        route: pattern.to_owned(),
+                        existing: existing.to_owned(),
+                        method: handler.method,
+                    });
+                }
+
+                pending.push((pattern, variant, handler));
             }
         }
-        Route::Dynamic {
-            route,
-            dynamic_component_positions: positions,
+
+        for (pattern, variant, handler) in pending {
+            self.insert_variant(pattern, &variant, handler.clone());
+        }
+
+        Ok(())
+    }
+
+    fn insert_variant(
+        &mut self,
+        pattern: &str,
+        segments: &[Segment],
+        handler: RegisteredRoute<Arc<State>, T>,
+    ) {
+        let mut node = &mut self.root;
+        let mut param_names = Vec::new();
+
+        for segment in segments {
+            node = match *segment {
+                Segment::Static(segment) => node.statics.entry(segment.to_owned()).or_default(),
+                Segment::Param { name, .. } => {
+                    param_names.push(name.to_owned());
+                    node.param.get_or_insert_with(Default::default)
+                }
+                Segment::CatchAll(name) => {
+                    param_names.push(name.to_owned());
+                    break;
+                }
+            };
+        }
+
+        node.endpoints_for_mut(segments).push(Endpoint {
+            pattern: pattern.to_owned(),
+            param_names,
             handler,
-        } => {
-            let route_len = route.components().count();
-            let request_len = req_route.components().count();
+        });
+        self.len += 1;
+    }
+
+    /// Calls `f` on every route registered for this pattern (one per method and variant).
+    /// Returns how many routes there were.
+    pub fn for_each_mut(
+        &mut self,
+        pattern: &str,
+        mut f: impl FnMut(&mut RegisteredRoute<Arc<State>, T>),
+    ) -> usize {
+        let Ok(segments) = parse_pattern(pattern) else {
+            return 0;
+        };
 
-            let mut handler = handler.clone();
+        let mut count = 0;
+        for variant in expand_optional(&segments) {
+            let Some(node) = self.root.get_mut(&variant) else {
+                continue;
+            };
 
-            if route_len != request_len {
-                return None;
+            for endpoint in node.endpoints_for_mut(&variant) {
+                f(&mut endpoint.handler);
+                count += 1;
             }
+        }
 
-            if req_route
-                .components()
-                .zip(route.components())
-                .enumerate()
-                .map(|(curr_pos, (request_component, route_component))| {
-                    if let Some(dynamic_part) = positions.get(&curr_pos) {
-                        let key = dynamic_part.to_string_lossy();
-                        let key = key
-                            .strip_prefix(':')
-                            .expect("route parameter must have a :");
-
-                        handler.params.insert(
-                            key.to_owned(),
-                            request_component.as_os_str().to_string_lossy().to_string(),
-                        );
-                        true
-                    } else {
-                        request_component == route_component
-                    }
-                })
-                .all(|matched| matched)
-            {
-                Some(handler)
-            } else {
-                None
+        count
+    }
+
+    /// Finds the route for a path normalized by [`normalize_path`](crate::url::normalize_path),
+    /// filling in its decoded params
+    pub fn find(&self, method: Method, path: &str, local_addr: Address) -> Lookup<State, T> {
+        let decoded = segments(path).map(decode_segment).collect::<Vec<_>>();
+        let path = decoded.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        let mut values = Vec::new();
+        let mut path_matched = false;
+
+        let accept = |endpoint: &Endpoint<State, T>| endpoint.handler.method == method;
+        let found = self
+            .root
+            .find(&path, &mut values, &mut path_matched, &accept, local_addr);
+
+        match found {
+            Some(endpoint) => {
+                let mut handler = endpoint.handler.clone();
+                handler.params = endpoint.param_names.iter().cloned().zip(values).collect();
+                handler.pattern = Some(endpoint.pattern.clone());
+
+                Lookup::Found(handler)
+            }
+            None if path_matched => Lookup::MethodNotAllowed,
+            None => Lookup::NotFound,
+        }
+    }
+}
+
+pub enum Lookup<State, T: Transport> {
+    Found(RegisteredRoute<Arc<State>, T>),
+    MethodNotAllowed,
+    NotFound,
+}
+
+impl<State, T: Transport> Node<State, T> {
+    /// The node a pattern variant ends on, catch-alls are stored on the node before them
+    fn get(&self, segments: &[Segment]) -> Option<&Self> {
+        segments
+            .iter()
+            .try_fold(self, |node, segment| match *segment {
+                Segment::Static(segment) => node.statics.get(segment),
+                Segment::Param { .. } => node.param.as_deref(),
+                Segment::CatchAll(_) => Some(node),
+            })
+    }
+
+    fn get_mut(&mut self, segments: &[Segment]) -> Option<&mut Self> {
+        segments
+            .iter()
+            .try_fold(self, |node, segment| match *segment {
+                Segment::Static(segment) => node.statics.get_mut(segment),
+                Segment::Param { .. } => node.param.as_deref_mut(),
+                Segment::CatchAll(_) => Some(node),
+            })
+    }
+
+    fn endpoints_for(&self, segments: &[Segment]) -> &[Endpoint<State, T>] {
+        match segments.last() {
+            Some(Segment::CatchAll(_)) => &self.catch_all,
+            _ => &self.endpoints,
+        }
+    }
+
+    fn endpoints_for_mut(&mut self, segments: &[Segment]) -> &mut Vec<Endpoint<State, T>> {
+        match segments.last() {
+            Some(Segment::CatchAll(_)) => &mut self.catch_all,
+            _ => &mut self.endpoints,
+        }
+    }
+
+    /// Depth first search, trying static children, then the param child, then catch-alls.
+    /// As this is a tree, the search reaches every node at most once.
+    ///
+    /// On success `values` holds the segments captured by params along the way.
+    /// `path_matched` records whether any route matched the path at all,
+    /// to tell "wrong method" apart from "no such route".
+    fn find<'node>(
+        &'node self,
+        path: &[&str],
+        values: &mut Vec<String>,
+        path_matched: &mut bool,
+        accept: &dyn Fn(&Endpoint<State, T>) -> bool,
+        local_addr: Address,
+    ) -> Option<&'node Endpoint<State, T>> {
+        if let Some((segment, rest)) = path.split_first() {
+            if let Some(child) = self.statics.get(*segment) {
+                if let Some(found) = child.find(rest, values, path_matched, accept, local_addr) {
+                    return Some(found);
+                }
+            }
+
+            if let Some(child) = &self.param {
+                values.push(segment.to_string());
+                if let Some(found) = child.find(rest, values, path_matched, accept, local_addr) {
+                    return Some(found);
+                }
+                values.pop();
             }
+        } else if let Some(found) = select(&self.endpoints, path_matched, accept, local_addr) {
+            return Some(found);
         }
-    })
+
+        let found = select(&self.catch_all, path_matched, accept, local_addr)?;
+        values.push(path.join("/"));
+        Some(found)
+    }
+}
+
+/// Picks the endpoint accepting the request among those visible on this listener
+fn select<'node, State, T: Transport>(
+    endpoints: &'node [Endpoint<State, T>],
+    path_matched: &mut bool,
+    accept: &dyn Fn(&Endpoint<State, T>) -> bool,
+    local_addr: Address,
+) -> Option<&'node Endpoint<State, T>> {
+    endpoints
+        .iter()
+        .filter(|endpoint| {
+            endpoint
+                .handler
+                .listener
+                .is_none_or(|only_on| only_on == local_addr)
+        })
+        .find(|endpoint| {
+            *path_matched = true;
+            accept(endpoint)
+        })
+}
+
+pub fn router<State: 'static + Send + Sync, T: Transport>(
+    routes: &Router<State, T>,
+    not_found_handler: RegisteredRoute<Arc<State>, T>,
+    method_not_allowed_handler: RegisteredRoute<Arc<State>, T>,
+    current_request: &HttpRequest,
+    local_addr: Address,
+) -> RegisteredRoute<Arc<State>, T> {
+    match routes.find(current_request.method, &current_request.route, local_addr) {
+        Lookup::Found(handler) => handler,
+        Lookup::MethodNotAllowed => method_not_allowed_handler,
+        Lookup::NotFound => not_found_handler,
+    }
 }
@@ -0,0 +1,269 @@
+use super::{Handler, HttpResult};
+use crate::{
+    http::{
+        consts::headers::{ACCEPT_RANGES, CONTENT_TYPE},
+        requested_ranges, ETag, HttpError, HttpRequest, HttpResponse, Ranges, StatusCode,
+    },
+    url::{self, decode_segment},
+};
+use std::{
+    fs::{self, File, Metadata},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Serves the files below a root directory.
+///
+/// Register it with a catch-all, e.g. through [`Server::static_files`](super::Server::static_files)
+/// or as `server.get("/assets/*", StaticFiles::new("web"), vec![])`.
+/// The catch-all (or the only route param) is the path of the file relative to the root.
+///
+/// Paths are confined to the root, directories are served through their index file and files
+/// larger than the [buffer limit](StaticFiles::buffer_limit) are streamed from disk.
+/// `Range` requests are answered with the requested parts of the file.
+/// Responses carry an `ETag` and `Last-Modified`, so clients can revalidate with a 304.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: Option<String>,
+    hidden_files: HiddenFiles,
+    symlinks: Symlinks,
+    buffer_limit: u64,
+}
+
+/// Whether files and directories starting with `.` are served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HiddenFiles {
+    /// They are treated as missing
+    #[default]
+    Deny,
+    Allow,
+}
+
+/// How symlinks below the root are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symlinks {
+    /// Followed as long as the target is inside the root
+    #[default]
+    WithinRoot,
+    /// Followed wherever they point
+    Follow,
+    /// Any symlink on the path is treated as missing
+    Deny,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index: Some("index.html".to_owned()),
+            hidden_files: HiddenFiles::default(),
+            symlinks: Symlinks::default(),
+            buffer_limit: 1024 * 1024,
+        }
+    }
+
+    /// The file served for directories, `None` to answer them with a 404
+    pub fn index(mut self, index: Option<&str>) -> Self {
+        self.index = index.map(str::to_owned);
+        self
+    }
+
+    pub fn hidden_files(mut self, hidden_files: HiddenFiles) -> Self {
+        self.hidden_files = hidden_files;
+        self
+    }
+
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Files up to this size are read into memory, larger ones are streamed
+    pub fn buffer_limit(mut self, bytes: u64) -> Self {
+        self.buffer_limit = bytes;
+        self
+    }
+
+    /// Serves the file at `path`, relative to the root
+    pub fn serve(&self, req: &HttpRequest, path: &str) -> HttpResult {
+        let not_found = || HttpError::not_found(format!("File {path} not found"));
+
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        if !segments.iter().all(|segment| self.allowed(segment)) {
+            return Err(not_found().into());
+        }
+
+        let root = self.root.canonicalize().map_err(|_| not_found())?;
+        let mut file_path = segments.iter().fold(root.clone(), |path, s| path.join(s));
+        let mut metadata = self.metadata(&root, &file_path).ok_or_else(not_found)?;
+
+        if metadata.is_dir() {
+            let index = self.index.as_ref().ok_or_else(not_found)?;
+
+            // Relative links in the index only work if the URL ends with a slash
+            if !req.route.ends_with('/') {
+                return Ok(HttpResponse::builder()
+                    .status(StatusCode::MovedPermanently)
+                    .header("Location", directory_location(&req.route))
+                    .build());
+            }
+
+            file_path.push(index);
+            metadata = self.metadata(&root, &file_path).ok_or_else(not_found)?;
+        }
+
+        if !metadata.is_file() {
+            return Err(not_found().into());
+        }
+
+        let modified = metadata.modified().ok();
+        let mut response = HttpResponse::builder()
+            .etag(&etag(&metadata, modified))
+            .header(ACCEPT_RANGES, "bytes".to_owned())
+            .header(CONTENT_TYPE, mime_type(&file_path).to_owned());
+        if let Some(modified) = modified {
+            response = response.last_modified(modified);
+        }
+
+        // Checked before the file is read, so a 304 doesn't touch its content
+        let mut response = response.build().conditional(req);
+        if response.status != StatusCode::Ok {
+            return Ok(response);
+        }
+
+        let len = metadata.len();
+        match requested_ranges(req, &response, len) {
+            Ranges::Full if len <= self.buffer_limit => {
+                response.body = Some(fs::read(&file_path)?);
+            }
+            ranges => response.set_ranges(ranges, File::open(&file_path)?, len)?,
+        }
+
+        Ok(response)
+    }
+
+    /// Whether a single decoded path segment may be served
+    fn allowed(&self, segment: &str) -> bool {
+        // Rejects `.`, `..`, separators and anything else that isn't a plain name on this platform
+        let mut components = Path::new(segment).components();
+        let plain = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(name)), None) if name == segment
+        );
+
+        plain
+            && !segment.contains(['/', '\\'])
+            && (self.hidden_files == HiddenFiles::Allow || !segment.starts_with('.'))
+    }
+
+    /// The metadata of `path` if it exists and the symlink policy allows it
+    fn metadata(&self, root: &Path, path: &Path) -> Option<Metadata> {
+        match self.symlinks {
+            Symlinks::Follow => {}
+            Symlinks::WithinRoot => {
+                if !path.canonicalize().ok()?.starts_with(root) {
+                    return None;
+                }
+            }
+            Symlinks::Deny => {
+                let relative = path.strip_prefix(root).ok()?;
+                let mut current = root.to_path_buf();
+                for component in relative.components() {
+                    current.push(component);
+                    if fs::symlink_metadata(&current).ok()?.is_symlink() {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        fs::metadata(path).ok()
+    }
+}
+
+impl<State> Handler<State, ()> for StaticFiles {
+    fn handle(&self, _: Arc<State>, req: HttpRequest) -> HttpResult {
+        let path = match req.path_params.get("*") {
+            Some(path) => path,
+            None if req.path_params.len() == 1 => req.path_params.values().next().unwrap(),
+            None => "",
+        };
+
+        self.serve(&req, path)
+    }
+}
+
+/// Where a directory without trailing slash redirects to.
+///
+/// Built from the normalized route instead of the raw one, which could start with `//` and then
+/// be taken as a different host. Every segment is escaped again, the result always starts with
+/// a single `/`.
+fn directory_location(route: &str) -> String {
+    let mut location = route.split('/').filter(|segment| !segment.is_empty()).fold(
+        String::new(),
+        |location, segment| {
+            location + "/" + &url::encode(&decode_segment(segment), url::Component::PathSegment)
+        },
+    );
+    location.push('/');
+    location
+}
+
+/// A strong validator from the size and modification time, like most web servers use
+fn etag(metadata: &Metadata, modified: Option<SystemTime>) -> ETag {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    ETag::strong(&format!(
+        "{:x}-{:x}-{:x}",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len()
+    ))
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,138 @@
+use super::Server;
+use std::{
+    env,
+    io::{self, ErrorKind},
+    net::{TcpListener, ToSocketAddrs},
+    ops::Range,
+    os::{
+        fd::{BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        raw::c_int,
+    },
+    process,
+};
+
+extern "C" {
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+/// `fcntl` command reading the descriptor flags, the same on every unix
+const F_GETFD: c_int = 1;
+
+/// systemd passes sockets starting at this file descriptor (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// More inherited sockets than this are treated as a broken environment
+const MAX_LISTEN_FDS: u16 = 1024;
+
+/// Takes the TCP listeners passed to this process by systemd socket activation.
+///
+/// Follows the `sd_listen_fds` protocol: the sockets are only picked up if `LISTEN_PID`
+/// matches this process. The environment variables are removed afterwards so the
+/// descriptors are only ever taken once and don't leak into child processes.
+/// Returns an empty list when the process was not socket activated.
+///
+/// Modifying the environment isn't thread safe, so this has to be called before
+/// any threads are spawned.
+pub fn systemd_listeners() -> io::Result<Vec<TcpListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let fds = listen_fds(pid.as_deref(), fds.as_deref(), process::id())?;
+    take_listeners(fds)
+}
+
+/// The descriptors passed in through `LISTEN_PID` and `LISTEN_FDS`,
+/// empty if they are meant for another process or not set
+pub(super) fn listen_fds(
+    pid: Option<&str>,
+    fds: Option<&str>,
+    own_pid: u32,
+) -> io::Result<Range<RawFd>> {
+    let none = LISTEN_FDS_START..LISTEN_FDS_START;
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(own_pid) {
+        return Ok(none);
+    }
+    let Some(fds) = fds else {
+        return Ok(none);
+    };
+
+    let invalid = || {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid LISTEN_FDS value '{fds}'"),
+        )
+    };
+    let count = fds
+        .parse::<u16>()
+        .ok()
+        .filter(|count| *count <= MAX_LISTEN_FDS)
+        .ok_or_else(invalid)?;
+    let end = LISTEN_FDS_START
+        .checked_add(RawFd::from(count))
+        .ok_or_else(invalid)?;
+
+    Ok(LISTEN_FDS_START..end)
+}
+
+/// Takes ownership of the TCP listeners at `fds`.
+///
+/// Every descriptor is checked to be open and then through a duplicate before any of them are
+/// taken over, so if one isn't a TCP socket the inherited descriptors are all left open as they were.
+/// The listeners end up on the duplicates, which are close-on-exec.
+pub(super) fn take_listeners(fds: Range<RawFd>) -> io::Result<Vec<TcpListener>> {
+    let listeners = fds
+        .clone()
+        .map(|fd| {
+            // SAFETY: F_GETFD only reads the flags and fails with EBADF if `fd` is not open
+            if unsafe { fcntl(fd, F_GETFD) } == -1 {
+                let e = io::Error::last_os_error();
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("inherited file descriptor {fd} is not open: {e}"),
+                ));
+            }
+            // SAFETY: `fd` was just checked to be open and stays open while it is borrowed,
+            // the originals are only closed below after all duplicates exist
+            let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+            let listener = TcpListener::from(borrowed.try_clone_to_owned()?);
+
+            // Unix sockets or non-socket descriptors fail here instead of on the first accept
+            listener.local_addr().map_err(|e| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("inherited file descriptor {fd} is not a TCP socket: {e}"),
+                )
+            })?;
+
+            Ok(listener)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    for fd in fds {
+        // SAFETY: systemd hands these descriptors to us exclusively and the environment
+        // variables were removed, so nothing else takes ownership of them
+        drop(unsafe { OwnedFd::from_raw_fd(fd) });
+    }
+
+    Ok(listeners)
+}
+
+impl<State: 'static + Send + Sync> Server<State> {
+    /// Starts serving on the sockets passed in by systemd socket activation.
+    ///
+    /// Falls back to binding `fallback` when the process was not socket activated,
+    /// so the same binary can be run by hand during development.
+    pub fn start_socket_activated(self, fallback: impl ToSocketAddrs) -> io::Result<()> {
+        let listeners = systemd_listeners()?;
+
+        if listeners.is_empty() {
+            self.start(fallback)
+        } else {
+            self.start_with_listeners(listeners)
+        }
+    }
+}
@@ -0,0 +1,1139 @@
+use super::{
+    conditional_requests,
+    default_handlers::{self, make_default},
+    middleware::Middleware,
+    routing::{Lookup, Router},
+    unique, HiddenFiles, HttpResult, MiddlewareResult, Next, RegisteredRoute, RouteError,
+    RouteGroup, Server, StaticFiles, Symlinks,
+};
+use crate::{
+    http::{
+        ETag, HeaderMap, HttpError, HttpRequest, HttpResponse, Method, MultipartLimits, StatusCode,
+    },
+    transport::Address,
+    url::{normalize_path, EncodedSlashes},
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+fn route(method: Method) -> RegisteredRoute<Arc<()>, TcpStream> {
+    let mut route = make_default(default_handlers::not_found);
+    route.method = method;
+    route
+}
+
+fn router(routes: &[(Method, &str)]) -> Router<(), TcpStream> {
+    let mut router = Router::default();
+    for (method, pattern) in routes {
+        router.insert(pattern, route(*method)).unwrap();
+    }
+    router
+}
+
+/// Returns the params of the matched route
+fn find(
+    router: &Router<(), TcpStream>,
+    method: Method,
+    path: &str,
+) -> Option<HashMap<String, String>> {
+    match router.find(method, path, Address::Unknown) {
+        Lookup::Found(route) => Some(route.params),
+        Lookup::MethodNotAllowed | Lookup::NotFound => None,
+    }
+}
+
+fn params<const N: usize>(params: [(&str, &str); N]) -> Option<HashMap<String, String>> {
+    Some(
+        params
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect(),
+    )
+}
+
+#[test]
+fn test_static_route_before_param() {
+    for routes in [
+        [(Method::Get, "/users/:id"), (Method::Get, "/users/me")],
+        [(Method::Get, "/users/me"), (Method::Get, "/users/:id")],
+    ] {
+        let router = router(&routes);
+
+        assert_eq!(find(&router, Method::Get, "/users/me"), params([]));
+        assert_eq!(
+            find(&router, Method::Get, "/users/5"),
+            params([("id", "5")])
+        );
+    }
+}
+
+#[test]
+fn test_backtrack_into_param() {
+    let router = router(&[
+        (Method::Get, "/users/me/settings"),
+        (Method::Get, "/users/:id/posts/:post"),
+    ]);
+
+    assert_eq!(
+        find(&router, Method::Get, "/users/me/posts/3"),
+        params([("id", "me"), ("post", "3")])
+    );
+    assert_eq!(find(&router, Method::Get, "/users/me/settings"), params([]));
+}
+
+#[test]
+fn test_many_ambiguous_params() {
+    const DEPTH: usize = 10;
+
+    // Every combination of `s` and a param on each level, the worst case for backtracking
+    let patterns = (0..1 << DEPTH)
+        .map(|mask: usize| {
+            let segments = (0..DEPTH)
+                .map(|level| match mask >> level & 1 {
+                    0 => "s".to_owned(),
+                    _ => format!(":p{level}"),
+                })
+                .collect::<Vec<_>>();
+            format!("/{}/end", segments.join("/"))
+        })
+        .collect::<Vec<_>>();
+    let router = router(
+        &patterns
+            .iter()
+            .map(|pattern| (Method::Get, pattern.as_str()))
+            .collect::<Vec<_>>(),
+    );
+    assert_eq!(router.len(), 1 << DEPTH);
+
+    let all_static = format!("{}/end", "/s".repeat(DEPTH));
+    assert_eq!(find(&router, Method::Get, &all_static), params([]));
+
+    // Only the last level falls back to its param
+    let last_differs = format!("{}/x/end", "/s".repeat(DEPTH - 1));
+    assert_eq!(
+        find(&router, Method::Get, &last_differs),
+        params([("p9", "x")])
+    );
+
+    // Every branch is tried before giving up
+    let no_match = format!("{}/nope", "/s".repeat(DEPTH));
+    assert_eq!(find(&router, Method::Get, &no_match), None);
+}
+
+#[test]
+fn test_root_and_trailing_slash() {
+    let router = router(&[(Method::Get, "/"), (Method::Get, "/hello")]);
+
+    assert_eq!(find(&router, Method::Get, "/"), params([]));
+    assert_eq!(find(&router, Method::Get, "/hello/"), params([]));
+    assert_eq!(find(&router, Method::Get, "/hello/there"), None);
+}
+
+#[test]
+fn test_method_not_allowed() {
+    let router = router(&[
+        (Method::Get, "/users/me"),
+        (Method::Delete, "/users/:id"),
+        (Method::Post, "/posts"),
+    ]);
+
+    assert!(matches!(
+        router.find(Method::Get, "/posts", Address::Unknown),
+        Lookup::MethodNotAllowed
+    ));
+    assert!(matches!(
+        router.find(Method::Get, "/nothing", Address::Unknown),
+        Lookup::NotFound
+    ));
+
+    // the static route doesn't accept DELETE, so the param route gets it
+    assert_eq!(
+        find(&router, Method::Delete, "/users/me"),
+        params([("id", "me")])
+    );
+}
+
+#[test]
+fn test_same_path_different_methods() {
+    let router = router(&[(Method::Get, "/items"), (Method::Post, "/items")]);
+
+    assert_eq!(router.len(), 2);
+    assert!(find(&router, Method::Get, "/items").is_some());
+    assert!(find(&router, Method::Post, "/items").is_some());
+}
+
+#[test]
+fn test_route_only_on_listener() {
+    let admin = Address::Tcp("127.0.0.1:9000".parse().unwrap());
+    let public = Address::Tcp("127.0.0.1:8000".parse().unwrap());
+
+    let mut router = router(&[(Method::Get, "/admin")]);
+    router.for_each_mut("/admin", |route| route.listener = Some(admin));
+
+    assert!(matches!(
+        router.find(Method::Get, "/admin", admin),
+        Lookup::Found(_)
+    ));
+    assert!(matches!(
+        router.find(Method::Get, "/admin", public),
+        Lookup::NotFound
+    ));
+}
+
+#[test]
+fn test_unique_addrs() {
+    let a: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+    let b: SocketAddr = "[::1]:8000".parse().unwrap();
+    let c: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+
+    // Binding `a` twice would fail with AddrInUse
+    assert_eq!(unique([a, b, a, c, b]), [a, b, c]);
+    assert_eq!(unique([c, c]), [c]);
+}
+
+#[test]
+fn test_many_routes() {
+    let patterns = (0..5000)
+        .map(|i| format!("/api/v{}/resource{}/:id", i % 7, i))
+        .collect::<Vec<_>>();
+
+    let mut router = Router::default();
+    for pattern in &patterns {
+        router.insert(pattern, route(Method::Get)).unwrap();
+    }
+
+    assert_eq!(router.len(), 5000);
+    assert_eq!(
+        find(&router, Method::Get, "/api/v1/resource4999/abc"),
+        params([("id", "abc")])
+    );
+    assert_eq!(find(&router, Method::Get, "/api/v4/resource4999/abc"), None);
+}
+
+#[test]
+fn test_catch_all() {
+    let router = router(&[
+        (Method::Get, "/static/*path"),
+        (Method::Get, "/static/index.html"),
+        (Method::Get, "/files/**"),
+    ]);
+
+    assert_eq!(
+        find(&router, Method::Get, "/static/css/main.css"),
+        params([("path", "css/main.css")])
+    );
+    assert_eq!(find(&router, Method::Get, "/static/index.html"), params([]));
+    assert_eq!(
+        find(&router, Method::Get, "/static"),
+        params([("path", "")])
+    );
+    assert_eq!(
+        find(&router, Method::Get, "/files/a/b"),
+        params([("*", "a/b")])
+    );
+}
+
+#[test]
+fn test_decoded_params() {
+    let router = router(&[
+        (Method::Get, "/files/:name"),
+        (Method::Get, "/static/*path"),
+        (Method::Get, "/a b"),
+    ]);
+
+    for (path, expected) in [
+        ("/files/a b", params([("name", "a b")])),
+        ("/files/a%2Fb", params([("name", "a/b")])),
+        ("/files/100%25", params([("name", "100%")])),
+        ("/static/x%2Fy/z", params([("path", "x/y/z")])),
+        ("/a b", params([])),
+    ] {
+        let normalized = normalize_path(path, EncodedSlashes::Preserve).unwrap();
+        assert_eq!(find(&router, Method::Get, &normalized), expected);
+    }
+
+    let normalized = normalize_path("/files/a%252Fb", EncodedSlashes::Preserve).unwrap();
+    assert_eq!(
+        find(&router, Method::Get, &normalized),
+        params([("name", "a%2Fb")])
+    );
+}
+
+#[test]
+fn test_param_before_catch_all() {
+    let router = router(&[(Method::Get, "/a/*rest"), (Method::Get, "/a/:id")]);
+
+    assert_eq!(find(&router, Method::Get, "/a/1"), params([("id", "1")]));
+    assert_eq!(
+        find(&router, Method::Get, "/a/1/2"),
+        params([("rest", "1/2")])
+    );
+}
+
+#[test]
+fn test_optional_param() {
+    let router = router(&[(Method::Get, "/posts/:id?"), (Method::Get, "/:lang?/about")]);
+
+    assert_eq!(find(&router, Method::Get, "/posts"), params([]));
+    assert_eq!(
+        find(&router, Method::Get, "/posts/4"),
+        params([("id", "4")])
+    );
+    assert_eq!(find(&router, Method::Get, "/posts/4/5"), None);
+    assert_eq!(find(&router, Method::Get, "/about"), params([]));
+    assert_eq!(
+        find(&router, Method::Get, "/de/about"),
+        params([("lang", "de")])
+    );
+}
+
+fn insert_err(routes: &[(Method, &str)], method: Method, pattern: &str) -> RouteError {
+    router(routes)
+        .insert(pattern, route(method))
+        .expect_err("route should have been rejected")
+}
+
+#[test]
+fn test_invalid_patterns() {
+    assert_eq!(
+        insert_err(&[], Method::Get, "/a//b"),
+        RouteError::EmptySegment {
+            route: "/a//b".to_owned()
+        }
+    );
+    assert_eq!(
+        insert_err(&[], Method::Get, "/a/:/b"),
+        RouteError::MissingParamName {
+            route: "/a/:/b".to_owned()
+        }
+    );
+    assert_eq!(
+        insert_err(&[], Method::Get, "/a/:?"),
+        RouteError::MissingParamName {
+            route: "/a/:?".to_owned()
+        }
+    );
+    assert_eq!(
+        insert_err(&[], Method::Get, "/a/:id/b/*id"),
+        RouteError::DuplicateParam {
+            route: "/a/:id/b/*id".to_owned(),
+            name: "id".to_owned()
+        }
+    );
+    assert_eq!(
+        insert_err(&[], Method::Get, "/a/*rest/b"),
+        RouteError::CatchAllNotLast {
+            route: "/a/*rest/b".to_owned()
+        }
+    );
+}
+
+#[test]
+fn test_conflicting_routes() {
+    let conflict = |route: &str, existing: &str| RouteError::Conflict {
+        route: route.to_owned(),
+        existing: existing.to_owned(),
+        method: Method::Get,
+    };
+
+    assert_eq!(
+        insert_err(&[(Method::Get, "/a/:x")], Method::Get, "/a/:y"),
+        conflict("/a/:y", "/a/:x")
+    );
+    assert_eq!(
+        insert_err(&[(Method::Get, "/hello")], Method::Get, "/hello/"),
+        conflict("/hello/", "/hello")
+    );
+    assert_eq!(
+        insert_err(&[(Method::Get, "/posts")], Method::Get, "/posts/:id?"),
+        conflict("/posts/:id?", "/posts")
+    );
+    assert_eq!(
+        insert_err(&[(Method::Get, "/files/*path")], Method::Get, "/files/**"),
+        conflict("/files/**", "/files/*path")
+    );
+    // both optionals left out in turn results in the same single param route twice
+    assert_eq!(
+        insert_err(&[], Method::Get, "/:a?/:b?"),
+        conflict("/:a?/:b?", "/:a?/:b?")
+    );
+
+    assert_eq!(
+        conflict("/a/:y", "/a/:x").to_string(),
+        "GET route /a/:y conflicts with already registered route /a/:x"
+    );
+}
+
+#[test]
+fn test_failed_insert_registers_nothing() {
+    let mut router = router(&[(Method::Get, "/posts/5")]);
+
+    // the variant without the optional segment would be fine, the one with it isn't
+    assert!(router.insert("/posts/:id?", route(Method::Get)).is_ok());
+    assert!(router.insert("/things/:id?/5", route(Method::Get)).is_ok());
+    assert!(router.insert("/things/:x?/5", route(Method::Get)).is_err());
+
+    assert_eq!(router.len(), 5);
+}
+
+#[test]
+fn test_same_structure_different_methods() {
+    let router = router(&[(Method::Get, "/a/:x"), (Method::Delete, "/a/:y")]);
+
+    assert_eq!(find(&router, Method::Get, "/a/1"), params([("x", "1")]));
+    assert_eq!(find(&router, Method::Delete, "/a/1"), params([("y", "1")]));
+}
+
+#[test]
+fn test_same_route_on_different_listeners() {
+    let admin = Address::Tcp("127.0.0.1:9000".parse().unwrap());
+    let public = Address::Tcp("127.0.0.1:8000".parse().unwrap());
+    let on = |listener, method| {
+        let mut route = route(method);
+        route.listener = Some(listener);
+        route
+    };
+
+    let mut router = router(&[]);
+    router.insert("/status/:a", on(admin, Method::Get)).unwrap();
+    router
+        .insert("/status/:b", on(public, Method::Get))
+        .unwrap();
+
+    assert!(router.insert("/status/:c", on(admin, Method::Get)).is_err());
+    // an unrestricted route would be reachable on both listeners
+    assert!(router.insert("/status/:c", route(Method::Get)).is_err());
+
+    let param = |listener| match router.find(Method::Get, "/status/1", listener) {
+        Lookup::Found(route) => route.params.into_keys().collect::<Vec<_>>(),
+        _ => panic!("route not found"),
+    };
+    assert_eq!(param(admin), ["a"]);
+    assert_eq!(param(public), ["b"]);
+}
+
+#[test]
+fn test_try_route() {
+    let mut server = Server::<()>::new(());
+    server.try_get("/a/:x", ok, vec![]).unwrap();
+    server.try_post("/a/:y", ok, vec![]).unwrap();
+
+    assert_eq!(
+        server.try_get("/a/:y", ok, vec![]),
+        Err(RouteError::Conflict {
+            route: "/a/:y".to_owned(),
+            existing: "/a/:x".to_owned(),
+            method: Method::Get,
+        })
+    );
+    assert!(server.try_route(Method::Put, "/a//b", ok, vec![]).is_err());
+    assert_eq!(server.router.len(), 2);
+}
+
+fn ok(_: Arc<()>, _: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
+    Ok(HttpResponse::builder().build())
+}
+
+/// Appends the name of each middleware to the `trace` param so their order can be checked
+macro_rules! tracing_middleware {
+    ($name:ident) => {
+        fn $name(_: Arc<()>, req: &mut HttpRequest) -> MiddlewareResult {
+            req.path_params
+                .entry("trace".to_owned())
+                .or_default()
+                .push_str(stringify!($name));
+            MiddlewareResult::Continue
+        }
+    };
+}
+
+tracing_middleware!(a);
+tracing_middleware!(b);
+tracing_middleware!(c);
+
+/// Wraps the rest of the chain in parentheses
+fn paren(_: Arc<()>, mut req: HttpRequest, next: Next<Arc<()>>) -> HttpResult {
+    req.path_params
+        .entry("trace".to_owned())
+        .or_default()
+        .push('(');
+
+    let mut response = next.run(req)?;
+    response.body.get_or_insert_with(Vec::new).push(b')');
+    Ok(response)
+}
+
+/// Aborts in the middle of the chain
+fn stop(_: Arc<()>, _: &mut HttpRequest) -> MiddlewareResult {
+    MiddlewareResult::Abort(HttpResponse::builder().text("stop".to_owned()).build())
+}
+
+/// Runs the middlewares of a route, the handler responds with the trace
+fn trace(route: &RegisteredRoute<Arc<()>, TcpStream>) -> String {
+    let handler = |_, mut req: HttpRequest| {
+        let trace = req.path_params.remove("trace").unwrap_or_default();
+        Ok(HttpResponse::builder().text(trace).build())
+    };
+
+    let response = Next::new(Arc::new(()), &route.specific_middlewares, &handler)
+        .run(HttpRequest::default())
+        .unwrap();
+
+    String::from_utf8(response.body.unwrap_or_default()).unwrap()
+}
+
+#[test]
+fn test_group_prefixes() {
+    let mut group = RouteGroup::<(), TcpStream>::new();
+    group.get("/", ok, vec![]);
+    group.get("/users", ok, vec![]);
+    group.group("/admin/", |admin| {
+        admin.get("/", ok, vec![]);
+        admin.get("stats", ok, vec![]);
+    });
+
+    let routes: Vec<_> = group
+        .into_routes("/api/v1/")
+        .map(|(route, _)| route)
+        .collect();
+
+    assert_eq!(
+        routes,
+        [
+            "/api/v1",
+            "/api/v1/users",
+            "/api/v1/admin",
+            "/api/v1/admin/stats"
+        ]
+    );
+
+    let mut group = RouteGroup::<(), TcpStream>::new();
+    group.get("/", ok, vec![]);
+    let routes: Vec<_> = group.into_routes("/").map(|(route, _)| route).collect();
+    assert_eq!(routes, ["/"]);
+}
+
+#[test]
+fn test_group_middleware_order() {
+    let mut group = RouteGroup::<(), TcpStream>::new();
+    group.get("/outer", ok, vec![c]);
+    group.group("/inner", |inner| {
+        inner.get("/", ok, vec![c]);
+        inner.middleware(b);
+    });
+    // registered after the routes, still applies to all of them
+    group.middleware(a);
+
+    let traces: Vec<_> = group
+        .into_routes("")
+        .map(|(route, handler)| (route, trace(&handler)))
+        .collect();
+
+    assert_eq!(
+        traces,
+        [
+            ("/outer".to_owned(), "ac".to_owned()),
+            ("/inner".to_owned(), "abc".to_owned())
+        ]
+    );
+}
+
+#[test]
+fn test_group_listener_scope() {
+    let outer: std::net::SocketAddr = "127.0.0.1:1".parse().unwrap();
+    let inner: std::net::SocketAddr = "127.0.0.1:2".parse().unwrap();
+
+    let mut group = RouteGroup::<(), TcpStream>::new();
+    group.only_on(outer);
+    group.get("/a", ok, vec![]);
+    group.group("/b", |b| {
+        b.only_on(inner);
+        b.get("/", ok, vec![]);
+    });
+
+    let listeners: Vec<_> = group
+        .into_routes("")
+        .map(|(_, handler)| handler.listener)
+        .collect();
+
+    assert_eq!(
+        listeners,
+        [Some(Address::Tcp(outer)), Some(Address::Tcp(inner))]
+    );
+}
+
+#[test]
+fn test_try_mount() {
+    let mut server = Server::<()>::new(());
+    server.get("/api/users/:id", ok, vec![]);
+
+    let mut group = RouteGroup::new();
+    group.get("/posts", ok, vec![]);
+    group.get("/users/:name", ok, vec![]);
+    assert!(matches!(
+        server.try_mount("/api", group),
+        Err(RouteError::Conflict { .. })
+    ));
+    // the routes before the conflicting one weren't registered either
+    assert_eq!(server.router.len(), 1);
+
+    // routes of the same group conflicting with each other
+    let mut group = RouteGroup::new();
+    group.get("/a/:x", ok, vec![]);
+    group.get("/a/:y", ok, vec![]);
+    assert_eq!(
+        server.try_mount("/", group),
+        Err(RouteError::Conflict {
+            route: "/a/:y".to_owned(),
+            existing: "/a/:x".to_owned(),
+            method: Method::Get,
+        })
+    );
+    assert_eq!(server.router.len(), 1);
+
+    let mut group = RouteGroup::new();
+    group.get("/posts", ok, vec![]);
+    server.try_mount("/api", group).unwrap();
+    assert_eq!(server.router.len(), 2);
+}
+
+#[test]
+fn test_default_error_handler() {
+    let req = HttpRequest::default();
+
+    let response = default_handlers::error(&req, Box::new(HttpError::not_found("no such user")));
+    assert_eq!(response.status, StatusCode::NotFound);
+    assert_eq!(response.body.as_deref(), Some(b"no such user".as_ref()));
+
+    let response = default_handlers::error(&req, "something else".into());
+    assert_eq!(response.status, StatusCode::InternalServerError);
+}
+
+#[test]
+fn test_around_middleware() {
+    let mut group = RouteGroup::<(), TcpStream>::new();
+    group.around(paren);
+    group.middleware(a);
+    group.get("/", ok, vec![b]);
+    group.group("/nested", |nested| {
+        nested.around(paren);
+        nested.get("/", ok, vec![c]);
+        nested.get("/stop", ok, vec![stop, c]);
+    });
+
+    let traces: Vec<_> = group
+        .into_routes("")
+        .map(|(_, handler)| trace(&handler))
+        .collect();
+
+    // abort responses still pass back through the around middlewares before them
+    assert_eq!(traces, ["(ab)", "(a(c))", "stop))"]);
+}
+
+/// A directory below the system temp dir, removed again on drop
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("mttp-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn file(&self, path: &str, content: &str) -> &Self {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        self
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn get_file<const N: usize>(
+    files: &StaticFiles,
+    route: &str,
+    headers: [(&str, &str); N],
+) -> HttpResponse {
+    let req = HttpRequest {
+        raw_route: route.to_owned(),
+        route: normalize_path(route, EncodedSlashes::Reject).unwrap(),
+        headers: HeaderMap::from(headers),
+        ..Default::default()
+    };
+    let path = req.route.trim_start_matches('/').to_owned();
+
+    match files.serve(&req, &path) {
+        Ok(response) => response,
+        Err(e) => default_handlers::error(&req, e),
+    }
+}
+
+#[test]
+fn test_static_files() {
+    let dir = TestDir::new("static");
+    dir.file("index.html", "<h1>home</h1>")
+        .file("css/site.css", "body {}")
+        .file("docs/index.html", "docs")
+        .file("my files/index.html", "mine")
+        .file(".env", "SECRET=1")
+        .file(".git/config", "[core]");
+    let files = StaticFiles::new(&dir.0);
+
+    let response = get_file(&files, "/css/site.css", []);
+    assert_eq!(response.status, StatusCode::Ok);
+    assert_eq!(response.body.as_deref(), Some(b"body {}".as_ref()));
+    assert_eq!(
+        response.headers.get("Content-Type").map(String::as_str),
+        Some("text/css; charset=utf-8")
+    );
+
+    let response = get_file(&files, "/", []);
+    assert_eq!(response.body.as_deref(), Some(b"<h1>home</h1>".as_ref()));
+
+    let response = get_file(&files, "/docs", []);
+    assert_eq!(response.status, StatusCode::MovedPermanently);
+    assert_eq!(
+        response.headers.get("Location").map(String::as_str),
+        Some("/docs/")
+    );
+    let response = get_file(&files, "/docs/", []);
+    assert_eq!(response.body.as_deref(), Some(b"docs".as_ref()));
+
+    // The raw target must not end up in the redirect, `//evil.com/` would be another host
+    for (target, location) in [
+        ("//evil.com/../docs", "/docs/"),
+        ("//docs", "/docs/"),
+        ("/my%20files", "/my%20files/"),
+    ] {
+        let response = get_file(&files, target, []);
+        assert_eq!(response.status, StatusCode::MovedPermanently, "{target}");
+        assert_eq!(
+            response.headers.get("Location").map(String::as_str),
+            Some(location),
+            "{target}"
+        );
+    }
+
+    for missing in [
+        "/nope.txt",
+        "/.env",
+        "/.git/config",
+        "/css/../../etc/passwd",
+    ] {
+        let response = get_file(&files, missing, []);
+        assert_eq!(response.status, StatusCode::NotFound, "{missing}");
+    }
+
+    let files = StaticFiles::new(&dir.0)
+        .hidden_files(HiddenFiles::Allow)
+        .index(None);
+    assert_eq!(get_file(&files, "/.env", []).status, StatusCode::Ok);
+    assert_eq!(get_file(&files, "/docs/", []).status, StatusCode::NotFound);
+}
+
+#[test]
+fn test_static_files_outside_root() {
+    let dir = TestDir::new("static-root");
+    dir.file("public/a.txt", "a").file("secret.txt", "secret");
+    let files = StaticFiles::new(dir.0.join("public"));
+
+    // The handler is also safe when it's given an unnormalized path
+    let req = HttpRequest::default();
+    for path in ["../secret.txt", "a.txt/../../secret.txt", "./a.txt", "a\\b"] {
+        assert!(files.serve(&req, path).is_err(), "{path}");
+    }
+    assert!(files.serve(&req, "a.txt").is_ok());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_static_files_symlinks() {
+    let dir = TestDir::new("static-links");
+    dir.file("public/a.txt", "a").file("secret.txt", "secret");
+    let public = dir.0.join("public");
+    std::os::unix::fs::symlink(public.join("a.txt"), public.join("inside.txt")).unwrap();
+    std::os::unix::fs::symlink(dir.0.join("secret.txt"), public.join("outside.txt")).unwrap();
+
+    let status = |files: &StaticFiles, route| get_file(files, route, []).status;
+
+    let files = StaticFiles::new(&public);
+    assert_eq!(status(&files, "/inside.txt"), StatusCode::Ok);
+    assert_eq!(status(&files, "/outside.txt"), StatusCode::NotFound);
+
+    let files = StaticFiles::new(&public).symlinks(Symlinks::Follow);
+    assert_eq!(status(&files, "/outside.txt"), StatusCode::Ok);
+
+    let files = StaticFiles::new(&public).symlinks(Symlinks::Deny);
+    assert_eq!(status(&files, "/inside.txt"), StatusCode::NotFound);
+    assert_eq!(status(&files, "/a.txt"), StatusCode::Ok);
+}
+
+#[test]
+fn test_static_files_conditional() {
+    let dir = TestDir::new("static-cache");
+    dir.file("a.txt", "a");
+    let files = StaticFiles::new(&dir.0);
+
+    let response = get_file(&files, "/a.txt", []);
+    let etag = response.headers.get("ETag").unwrap().clone();
+    let modified = response.headers.get("Last-Modified").unwrap().clone();
+
+    let response = get_file(&files, "/a.txt", [("If-None-Match", &etag)]);
+    assert_eq!(response.status, StatusCode::NotModified);
+    assert_eq!(response.body, None);
+
+    let weak = format!("\"other\", W/{etag}");
+    let response = get_file(&files, "/a.txt", [("If-None-Match", &weak)]);
+    assert_eq!(response.status, StatusCode::NotModified);
+
+    let response = get_file(&files, "/a.txt", [("If-Modified-Since", &modified)]);
+    assert_eq!(response.status, StatusCode::NotModified);
+
+    // If-None-Match takes precedence over If-Modified-Since
+    let headers = [
+        ("If-None-Match", "\"other\""),
+        ("If-Modified-Since", &modified),
+    ];
+    assert_eq!(get_file(&files, "/a.txt", headers).status, StatusCode::Ok);
+
+    let old = "Thu, 01 Jan 1970 00:00:00 GMT";
+    let response = get_file(&files, "/a.txt", [("If-Modified-Since", old)]);
+    assert_eq!(response.status, StatusCode::Ok);
+}
+
+#[test]
+fn test_static_files_stream() {
+    let dir = TestDir::new("static-stream");
+    dir.file("big.bin", &"x".repeat(100));
+    let files = StaticFiles::new(&dir.0).buffer_limit(10);
+
+    let response = get_file(&files, "/big.bin", []);
+    assert_eq!(response.body, None);
+    assert_eq!(
+        response.headers.get("Content-Type").map(String::as_str),
+        Some("application/octet-stream")
+    );
+
+    let mut stream = response.stream.unwrap();
+    assert_eq!(stream.content_length(), Some(100));
+    let mut content = String::new();
+    stream.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "x".repeat(100));
+}
+
+#[test]
+fn test_static_files_route() {
+    let mut server = Server::new(());
+    server.static_files("/assets/", StaticFiles::new(Path::new("web")));
+
+    assert_eq!(
+        find(&server.router, Method::Get, "/assets/css/a.css"),
+        params([("*", "css/a.css")])
+    );
+}
+
+#[test]
+fn test_static_files_ranges() {
+    let dir = TestDir::new("static-ranges");
+    dir.file("video.mp4", "0123456789");
+
+    for buffer_limit in [0, 1024] {
+        let files = StaticFiles::new(&dir.0).buffer_limit(buffer_limit);
+
+        let response = get_file(&files, "/video.mp4", []);
+        assert_eq!(response.headers.get("Accept-Ranges").unwrap(), "bytes");
+        let etag = response.headers.get("ETag").unwrap().clone();
+
+        let mut response = get_file(&files, "/video.mp4", [("Range", "bytes=-3")]);
+        assert_eq!(response.status, StatusCode::PartialContent);
+        assert_eq!(
+            response.headers.get("Content-Range").unwrap(),
+            "bytes 7-9/10"
+        );
+        let mut content = String::new();
+        let mut stream = response.stream.take().unwrap();
+        stream.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "789");
+        assert_eq!(stream.content_length(), Some(3));
+
+        let mut response = get_file(
+            &files,
+            "/video.mp4",
+            [("Range", "bytes=0-1,5-6"), ("If-Range", &etag)],
+        );
+        assert_eq!(response.status, StatusCode::PartialContent);
+        let mut stream = response.stream.take().unwrap();
+        let mut content = String::new();
+        stream.read_to_string(&mut content).unwrap();
+        assert_eq!(stream.content_length(), Some(content.len() as u64));
+        assert!(content
+            .contains("Content-Type: video/mp4\r\nContent-Range: bytes 5-6/10\r\n\r\n56\r\n"));
+
+        let response = get_file(&files, "/video.mp4", [("Range", "bytes=10-")]);
+        assert_eq!(response.status, StatusCode::RangeNotSatisfiable);
+
+        let response = get_file(
+            &files,
+            "/video.mp4",
+            [("Range", "bytes=0-1"), ("If-Range", "\"stale\"")],
+        );
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+}
+
+#[test]
+fn test_conditional_requests_middleware() {
+    let handler = |_, req: HttpRequest| match req.route.as_str() {
+        "/tagged" => Ok(HttpResponse::builder()
+            .text("tagged".to_owned())
+            .header("ETag", "\"mine\"".to_owned())
+            .build()),
+        _ => Ok(HttpResponse::builder().text("hello".to_owned()).build()),
+    };
+    let middlewares = [Middleware::Around(conditional_requests)];
+    let run = |route: &str, headers: &[(&str, &str)]| {
+        let mut req = HttpRequest {
+            route: route.to_owned(),
+            ..Default::default()
+        };
+        for (key, value) in headers {
+            req.headers
+                .values
+                .insert(key.to_string(), value.to_string());
+        }
+        Next::new(Arc::new(()), &middlewares, &handler)
+            .run(req)
+            .unwrap()
+    };
+
+    let response = run("/", &[]);
+    assert_eq!(response.status, StatusCode::Ok);
+    let etag = response.headers.get("ETag").unwrap().clone();
+    assert_eq!(etag, ETag::from_body(b"hello").to_string());
+
+    let response = run("/", &[("If-None-Match", &etag)]);
+    assert_eq!(response.status, StatusCode::NotModified);
+    assert_eq!(response.body, None);
+
+    let response = run("/tagged", &[]);
+    assert_eq!(response.headers.get("ETag").unwrap(), "\"mine\"");
+    let response = run("/tagged", &[("If-None-Match", "\"mine\"")]);
+    assert_eq!(response.status, StatusCode::NotModified);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_remove_stale_socket() {
+    use super::unix::remove_stale_socket;
+    use std::{io::ErrorKind, os::unix::net::UnixListener};
+
+    let dir = TestDir::new("unix-stale");
+    let socket = dir.0.join("server.sock");
+
+    // Nothing to remove
+    remove_stale_socket(&socket).unwrap();
+
+    // Still listening, so it's not stale
+    let listener = UnixListener::bind(&socket).unwrap();
+    let err = remove_stale_socket(&socket).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AddrInUse);
+    assert!(socket.exists());
+
+    // The file is left behind once the listener is gone
+    drop(listener);
+    remove_stale_socket(&socket).unwrap();
+    assert!(!socket.exists());
+
+    dir.file("data.txt", "keep me");
+    let err = remove_stale_socket(&dir.0.join("data.txt")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(
+        fs::read_to_string(dir.0.join("data.txt")).unwrap(),
+        "keep me"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_bind_unix_mode() {
+    use super::unix::bind_unix;
+    use std::os::unix::{fs::PermissionsExt, net::UnixStream};
+
+    let dir = TestDir::new("unix-mode");
+    let socket = dir.0.join("server.sock");
+
+    let _listener = bind_unix(&socket, Some(0o600)).unwrap();
+    let mode = fs::symlink_metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    UnixStream::connect(&socket).unwrap();
+
+    // The private directory used for binding is cleaned up
+    let entries = fs::read_dir(&dir.0).unwrap().count();
+    assert_eq!(entries, 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_listen_fds() {
+    use super::systemd::listen_fds;
+
+    assert_eq!(listen_fds(Some("42"), Some("2"), 42).unwrap(), 3..5);
+    assert!(listen_fds(Some("42"), Some("2"), 43).unwrap().is_empty());
+    assert!(listen_fds(None, Some("2"), 42).unwrap().is_empty());
+    assert!(listen_fds(Some("42"), None, 42).unwrap().is_empty());
+
+    for count in ["-1", "abc", "2147483647", "99999"] {
+        assert!(listen_fds(Some("42"), Some(count), 42).is_err(), "{count}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_take_listeners() {
+    use super::systemd::take_listeners;
+    use std::os::{
+        fd::{AsRawFd, IntoRawFd, RawFd},
+        unix::net::UnixStream,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = listener.into_raw_fd();
+    let listeners = take_listeners(fd..fd + 1).unwrap();
+    assert_eq!(listeners[0].local_addr().unwrap(), addr);
+
+    // Not a TCP socket, the descriptor has to stay open and usable
+    let (mut a, mut b) = UnixStream::pair().unwrap();
+    let fd = a.as_raw_fd();
+    assert!(take_listeners(fd..fd + 1).is_err());
+    a.write_all(b"still open").unwrap();
+    let mut buf = [0; 10];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"still open");
+
+    // Never opened, so there is nothing to borrow
+    let fd = RawFd::MAX - 1;
+    assert!(take_listeners(fd..fd + 1).is_err());
+}
+
+/// Serves `server` on a free local port in the background
+fn serve(server: Server<()>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || server.start_with_listener(listener));
+    addr
+}
+
+/// Sends a raw request and returns the raw response
+fn send(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_error_handler_sees_matched_route() {
+    fn fail(_: Arc<()>, _: HttpRequest) -> HttpResult {
+        Err(HttpError::internal("failed").into())
+    }
+
+    fn error_handler(req: &HttpRequest, _: Box<dyn std::error::Error>) -> HttpResponse {
+        HttpResponse::builder()
+            .text(format!("{:?}", req.matched_route))
+            .build()
+    }
+
+    let mut server = Server::new(());
+    server.get("/users/:id", fail, vec![]);
+    server.error_handler(error_handler);
+    let addr = serve(server);
+
+    let response = send(addr, "GET /users/5 HTTP/1.1\r\n\r\n");
+    assert!(
+        response.contains("\r\n\r\nSome(\"/users/:id\")"),
+        "{response:?}"
+    );
+}
+
+#[test]
+fn test_stream_body_is_not_buffered() {
+    fn upload(mut req: HttpRequest) -> Result<String, HttpError> {
+        let limits = MultipartLimits {
+            max_file_size: 1024,
+            ..MultipartLimits::default()
+        };
+        for part in req.multipart_with_limits(limits)? {
+            part?;
+        }
+        Ok("uploaded".to_owned())
+    }
+
+    let mut server = Server::new(());
+    server.post("/upload", upload, vec![]);
+    server.stream_body("/upload");
+    let addr = serve(server);
+
+    // Announces far more than is ever sent, buffering the body first would wait for all of it
+    let body = format!(
+        "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n{}",
+        "a".repeat(2048)
+    );
+    let request = format!(
+        "POST /upload HTTP/1.1\r\n\
+         Content-Type: multipart/form-data; boundary=XyZ\r\n\
+         Content-Length: 100000000\r\n\r\n{body}"
+    );
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = vec![0; 1024];
+    let read = stream.read(&mut response).unwrap();
+    let response = String::from_utf8_lossy(&response[..read]);
+    assert!(response.starts_with("HTTP/1.1 413"), "{response:?}");
+}
+
+#[test]
+fn test_preconditions_before_modifying() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+    fn put(_: Arc<()>, req: HttpRequest) -> HttpResult {
+        req.check_preconditions(Some(&ETag::strong("v1")), None)?;
+        WRITES.fetch_add(1, Ordering::SeqCst);
+        Ok(HttpResponse::builder().text("written".to_owned()).build())
+    }
+
+    let mut server = Server::new(());
+    server.put("/doc", put, vec![]);
+    server.around(conditional_requests);
+    let addr = serve(server);
+
+    let response = send(addr, "PUT /doc HTTP/1.1\r\nIf-Match: \"v2\"\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 412"), "{response:?}");
+    assert_eq!(WRITES.load(Ordering::SeqCst), 0);
+
+    let response = send(addr, "PUT /doc HTTP/1.1\r\nIf-Match: \"v1\"\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"), "{response:?}");
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,93 @@
+use super::Server;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    process,
+};
+
+impl<State: 'static + Send + Sync> Server<State, UnixStream> {
+    /// Sets the file permissions (e.g. `0o660`) applied to the socket file created by [`Server::start_unix`]
+    pub fn unix_socket_permissions(&mut self, mode: u32) {
+        self.unix_socket_mode = Some(mode);
+    }
+
+    /// Binds a unix domain socket at `path` and starts serving requests on it
+    ///
+    /// A leftover socket file from a previous run is removed first,
+    /// but only if nothing is listening on it anymore.
+    pub fn start_unix(self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+
+        println!("Binding mttp server to unix:{}", path.display());
+        remove_stale_socket(path)?;
+        let socket = bind_unix(path, self.unix_socket_mode)?;
+
+        self.serve(socket)
+    }
+}
+
+/// Binds a listener at `path`, with the socket file set to `mode` before anyone can connect.
+///
+/// Binding directly and changing the mode afterwards would leave the socket reachable with
+/// the umask permissions for a moment, so it is bound in a private directory instead and
+/// moved into place once its mode is set.
+pub(super) fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    let Some(mode) = mode else {
+        return UnixListener::bind(path);
+    };
+
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not a valid socket path", path.display()),
+        )
+    })?;
+    let parent = path.parent().unwrap_or(Path::new(""));
+    let private_dir = parent.join(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+    let temp_path = private_dir.join(file_name);
+
+    let result = UnixListener::bind(&temp_path).and_then(|socket| {
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(mode))?;
+        fs::rename(&temp_path, path)?;
+        Ok(socket)
+    });
+
+    let _ = fs::remove_file(&temp_path);
+    fs::remove_dir(&private_dir)?;
+    result
+}
+
+pub(super) fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AddrInUse,
+            format!("{} is still in use by another process", path.display()),
+        ));
+    }
+
+    fs::remove_file(path)
+}
@@ -0,0 +1,110 @@
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// The address of one end of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address {
+    /// A TCP/IP socket address
+    Tcp(SocketAddr),
+    /// A unix domain socket, these don't carry a useful peer address
+    Unix,
+    /// The transport has no meaningful address (in-memory pipes for example)
+    Unknown,
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{addr}"),
+            Address::Unix => write!(f, "unix"),
+            Address::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// A bidirectional byte stream HTTP requests and websocket connections can run on.
+///
+/// Implemented for [`TcpStream`] by default. Implement this for your own
+/// streams (TLS wrappers, in-memory pipes, ...) to run the server on them.
+pub trait Transport: Read + Write + Send + Sized + 'static {
+    /// Shuts down the read half, the write half or both halves of the connection
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+
+    /// Creates a new independently owned handle to the same connection
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// The address of the remote end of the connection
+    fn peer_addr(&self) -> io::Result<Address>;
+}
+
+/// A source of incoming connections for the server
+pub trait Listener {
+    /// The kind of connection this listener produces
+    type Stream: Transport;
+
+    /// Blocks until a new connection is available
+    fn accept(&self) -> io::Result<Self::Stream>;
+
+    /// The address this listener is bound to
+    fn local_addr(&self) -> io::Result<Address>;
+}
+
+impl Transport for TcpStream {
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn peer_addr(&self) -> io::Result<Address> {
+        TcpStream::peer_addr(self).map(Address::Tcp)
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<Self::Stream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        TcpListener::local_addr(self).map(Address::Tcp)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn peer_addr(&self) -> io::Result<Address> {
+        Ok(Address::Unix)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept(&self) -> io::Result<Self::Stream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    fn local_addr(&self) -> io::Result<Address> {
+        Ok(Address::Unix)
+    }
+}
@@ -0,0 +1,118 @@
+use super::{
+    parse::{parse_scheme, Host, Url, UrlError},
+    urlencoding::{encode, Component},
+};
+
+/// Builds a [`Url`] from unescaped parts, everything is percent-encoded as needed
+#[derive(Debug, Clone, Default)]
+pub struct UrlBuilder {
+    scheme: Option<String>,
+    userinfo: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+impl Url {
+    pub fn builder() -> UrlBuilder {
+        UrlBuilder::default()
+    }
+}
+
+impl UrlBuilder {
+    pub fn scheme(mut self, scheme: &str) -> Self {
+        self.scheme = Some(scheme.to_owned());
+        self
+    }
+
+    pub fn userinfo(mut self, user: &str, password: Option<&str>) -> Self {
+        let mut userinfo = encode(user, Component::Userinfo).replace(':', "%3A");
+        if let Some(password) = password {
+            userinfo.push(':');
+            userinfo.push_str(&encode(password, Component::Userinfo));
+        }
+        self.userinfo = Some(userinfo);
+        self
+    }
+
+    /// A domain, an IPv4 address or an IPv6 address with or without brackets
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_owned());
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Appends `path`, `/` separates segments and everything else is escaped
+    pub fn path(mut self, path: &str) -> Self {
+        self.path.push_str(&encode(path, Component::Path));
+        self
+    }
+
+    /// Appends `/` and a single segment, a `/` inside `segment` is escaped
+    pub fn segment(mut self, segment: &str) -> Self {
+        self.path.push('/');
+        self.path.push_str(&encode(segment, Component::PathSegment));
+        self
+    }
+
+    /// Appends `key=value` to the query
+    pub fn query_param(mut self, key: &str, value: &str) -> Self {
+        let query = self.query.get_or_insert_with(String::new);
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str(&encode(key, Component::QueryParam));
+        query.push('=');
+        query.push_str(&encode(value, Component::QueryParam));
+        self
+    }
+
+    pub fn fragment(mut self, fragment: &str) -> Self {
+        self.fragment = Some(encode(fragment, Component::Fragment));
+        self
+    }
+
+    pub fn build(self) -> Result<Url, UrlError> {
+        let scheme = self.scheme.as_deref().map(parse_scheme).transpose()?;
+
+        let host = match self.host {
+            Some(host) if host.contains(':') && !host.starts_with('[') => {
+                Some(Host::parse(&format!("[{host}]"))?)
+            }
+            Some(host) => match Host::parse(&host) {
+                Ok(host) => Some(host),
+                // Anything that isn't an IP is a domain that may need escaping
+                Err(_) if !host.starts_with('[') => Some(Host::Domain(encode(
+                    &host.to_ascii_lowercase(),
+                    Component::Host,
+                ))),
+                Err(e) => return Err(e),
+            },
+            None => None,
+        };
+
+        let mut path = self.path;
+        if host.is_some() && !path.is_empty() && !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+        if host.is_none() && path.starts_with("//") {
+            return Err(UrlError::InvalidPath);
+        }
+
+        Ok(Url {
+            scheme,
+            userinfo: self.userinfo,
+            host,
+            port: self.port,
+            path,
+            query: self.query,
+            fragment: self.fragment,
+        })
+    }
+}
@@ -1,7 +1,14 @@
+mod builder;
+mod parse;
+mod path;
 mod queryparams;
 mod urlencoding;
 
+pub use builder::*;
+pub use parse::*;
+pub use path::*;
 pub use queryparams::*;
+pub use urlencoding::{decode_binary, decode_string, encode, percent_decode, Component};
 
 #[cfg(test)]
 mod test;
@@ -0,0 +1,280 @@
+use super::{urlencoding::Component, QueryParams};
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// A URI reference as defined in RFC 3986.
+///
+/// Absolute URLs (`https://example.com/a`) as well as relative ones (`/a?b`, `//host/a`) are
+/// supported. All components are kept percent-encoded, exactly as they appear in the URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub(super) scheme: Option<String>,
+    pub(super) userinfo: Option<String>,
+    pub(super) host: Option<Host>,
+    pub(super) port: Option<u16>,
+    pub(super) path: String,
+    pub(super) query: Option<String>,
+    pub(super) fragment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// A registered name like `example.com`, lowercased
+    Domain(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlError {
+    InvalidScheme,
+    InvalidHost,
+    InvalidPort,
+    /// A path without an authority can't start with `//`
+    InvalidPath,
+    /// A character that has to be percent-encoded in its component
+    InvalidCharacter(char),
+    /// A `%` that isn't followed by two hex digits
+    InvalidPercentEncoding,
+}
+
+impl Url {
+    pub fn parse(input: &str) -> Result<Self, UrlError> {
+        let (rest, fragment) = match input.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (input, None),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+
+        // A scheme is everything before the first `:`, as long as there is no `/` before it
+        let (scheme, rest) = match rest.find([':', '/']) {
+            Some(i) if rest[i..].starts_with(':') => {
+                (Some(parse_scheme(&rest[..i])?), &rest[i + 1..])
+            }
+            _ => (None, rest),
+        };
+
+        let (authority, path) = match rest.strip_prefix("//") {
+            Some(rest) => {
+                let end = rest.find('/').unwrap_or(rest.len());
+                (Some(&rest[..end]), &rest[end..])
+            }
+            None => (None, rest),
+        };
+
+        let (userinfo, host, port) = match authority {
+            Some(authority) => {
+                let (userinfo, host, port) = parse_authority(authority)?;
+                (userinfo, Some(host), port)
+            }
+            None => (None, None, None),
+        };
+
+        validate(path, Component::Path)?;
+        if let Some(query) = query {
+            validate(query, Component::Query)?;
+        }
+        if let Some(fragment) = fragment {
+            validate(fragment, Component::Fragment)?;
+        }
+
+        Ok(Self {
+            scheme,
+            userinfo: userinfo.map(str::to_owned),
+            host,
+            port,
+            path: path.to_owned(),
+            query: query.map(str::to_owned),
+            fragment: fragment.map(str::to_owned),
+        })
+    }
+
+    /// The lowercased scheme, `None` for relative URLs
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    pub fn userinfo(&self) -> Option<&str> {
+        self.userinfo.as_deref()
+    }
+
+    pub fn host(&self) -> Option<&Host> {
+        self.host.as_ref()
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// The explicit port, or the default one of the scheme if it has one
+    pub fn port_or_default(&self) -> Option<u16> {
+        self.port.or(match self.scheme()? {
+            "http" | "ws" => Some(80),
+            "https" | "wss" => Some(443),
+            _ => None,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The raw query without the `?`
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// The decoded query parameters
+    pub fn query_params(&self) -> QueryParams {
+        QueryParams::parse(self.query().unwrap_or_default())
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+}
+
+impl FromStr for Url {
+    type Err = UrlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Url::parse(s)
+    }
+}
+
+impl Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{scheme}:")?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, "//")?;
+            if let Some(userinfo) = &self.userinfo {
+                write!(f, "{userinfo}@")?;
+            }
+            write!(f, "{host}")?;
+            if let Some(port) = self.port {
+                write!(f, ":{port}")?;
+            }
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{query}")?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{fragment}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Host {
+    /// Parses a host as it appears in a URL, IPv6 addresses have to be in brackets
+    pub fn parse(input: &str) -> Result<Self, UrlError> {
+        if let Some(ip) = input.strip_prefix('[') {
+            let ip = ip.strip_suffix(']').ok_or(UrlError::InvalidHost)?;
+            return ip
+                .parse()
+                .map(Host::Ipv6)
+                .map_err(|_| UrlError::InvalidHost);
+        }
+
+        if let Ok(ip) = input.parse() {
+            return Ok(Host::Ipv4(ip));
+        }
+
+        validate(input, Component::Host).map_err(|_| UrlError::InvalidHost)?;
+        Ok(Host::Domain(input.to_ascii_lowercase()))
+    }
+}
+
+impl Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Host::Ipv4(ip) => write!(f, "{ip}"),
+            Host::Ipv6(ip) => write!(f, "[{ip}]"),
+            Host::Domain(domain) => write!(f, "{domain}"),
+        }
+    }
+}
+
+pub(super) fn parse_scheme(scheme: &str) -> Result<String, UrlError> {
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+
+    match valid {
+        true => Ok(scheme.to_ascii_lowercase()),
+        false => Err(UrlError::InvalidScheme),
+    }
+}
+
+fn parse_authority(authority: &str) -> Result<(Option<&str>, Host, Option<u16>), UrlError> {
+    let (userinfo, host_port) = match authority.rsplit_once('@') {
+        Some((userinfo, host_port)) => (Some(userinfo), host_port),
+        None => (None, authority),
+    };
+    if let Some(userinfo) = userinfo {
+        validate(userinfo, Component::Userinfo)?;
+    }
+
+    // The port is after the last `:`, unless that is inside the brackets of an IPv6 address
+    let (host, port) = match host_port.rfind([':', ']']) {
+        Some(i) if host_port[i..].starts_with(':') => (&host_port[..i], Some(&host_port[i + 1..])),
+        _ => (host_port, None),
+    };
+
+    let port = match port {
+        Some(port) if !port.is_empty() => {
+            if !port.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(UrlError::InvalidPort);
+            }
+            Some(port.parse().map_err(|_| UrlError::InvalidPort)?)
+        }
+        _ => None,
+    };
+
+    Ok((userinfo, Host::parse(host)?, port))
+}
+
+/// Checks that `input` only contains characters allowed in `component` and valid escapes
+pub(super) fn validate(input: &str, component: Component) -> Result<(), UrlError> {
+    let bytes = input.as_bytes();
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        if byte == b'%' {
+            let escape = bytes
+                .get(i + 1..i + 3)
+                .ok_or(UrlError::InvalidPercentEncoding)?;
+            if !escape.iter().all(u8::is_ascii_hexdigit) {
+                return Err(UrlError::InvalidPercentEncoding);
+            }
+        } else if !component.allows(byte) {
+            let c = input[i..].chars().next().expect("i is a char boundary");
+            return Err(UrlError::InvalidCharacter(c));
+        }
+    }
+
+    Ok(())
+}
+
+impl Display for UrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UrlError::InvalidScheme => write!(f, "Invalid URL scheme"),
+            UrlError::InvalidHost => write!(f, "Invalid URL host"),
+            UrlError::InvalidPort => write!(f, "Invalid URL port"),
+            UrlError::InvalidPath => write!(f, "A URL path without a host can't start with '//'"),
+            UrlError::InvalidCharacter(c) => write!(f, "Character {c:?} must be percent-encoded"),
+            UrlError::InvalidPercentEncoding => write!(f, "Invalid percent-encoding in URL"),
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
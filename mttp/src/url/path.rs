@@ -0,0 +1,98 @@
+use super::urlencoding::percent_decode;
+use std::{borrow::Cow, fmt::Display};
+
+/// What happens to a `%2F` in a request path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncodedSlashes {
+    /// The request is rejected with a 400
+    #[default]
+    Reject,
+    /// The slash is part of the segment instead of separating segments,
+    /// so `/files/a%2Fb` matches `/files/:name` with `name` being `a/b`
+    Preserve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// The path doesn't start with `/`
+    NotAbsolute,
+    InvalidPercentEncoding,
+    /// A segment doesn't decode to UTF-8
+    InvalidUtf8,
+    /// A `%00` anywhere in the path
+    NulByte,
+    /// A `%2F` while [`EncodedSlashes::Reject`] is set
+    EncodedSlash,
+}
+
+/// Normalizes a request path before it is routed.
+///
+/// Every segment is percent-decoded, then dot segments are removed as in RFC 3986 section 5.2.4,
+/// so `/a/%2E%2E/b` becomes `/b` and a path can never climb above `/`. Empty segments are dropped,
+/// a trailing slash is kept.
+///
+/// `%` and preserved slashes stay encoded as `%25` and `%2F`, so the segments of the result are
+/// unambiguous. Use [`decode_segment`] to get their actual value.
+pub fn normalize_path(path: &str, encoded_slashes: EncodedSlashes) -> Result<String, PathError> {
+    let path = path.strip_prefix('/').ok_or(PathError::NotAbsolute)?;
+
+    let mut segments: Vec<String> = Vec::new();
+    let mut trailing_slash = false;
+
+    for raw in path.split('/') {
+        let bytes = percent_decode(raw).ok_or(PathError::InvalidPercentEncoding)?;
+        let segment = String::from_utf8(bytes).map_err(|_| PathError::InvalidUtf8)?;
+
+        if segment.contains('\0') {
+            return Err(PathError::NulByte);
+        }
+        if segment.contains('/') && encoded_slashes == EncodedSlashes::Reject {
+            return Err(PathError::EncodedSlash);
+        }
+
+        trailing_slash = matches!(segment.as_str(), "" | "." | "..");
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment.replace('%', "%25").replace('/', "%2F")),
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if trailing_slash || segments.is_empty() {
+        normalized.push('/');
+    }
+
+    Ok(normalized)
+}
+
+/// Decodes a segment of a path returned by [`normalize_path`]
+pub fn decode_segment(segment: &str) -> Cow<'_, str> {
+    if !segment.contains('%') {
+        return Cow::Borrowed(segment);
+    }
+
+    percent_decode(segment)
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .map_or(Cow::Borrowed(segment), Cow::Owned)
+}
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathError::NotAbsolute => write!(f, "The path must start with '/'"),
+            PathError::InvalidPercentEncoding => write!(f, "Invalid percent-encoding in path"),
+            PathError::InvalidUtf8 => write!(f, "The path is not valid UTF-8"),
+            PathError::NulByte => write!(f, "The path contains a NUL byte"),
+            PathError::EncodedSlash => write!(f, "Encoded slashes are not allowed in the path"),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
@@ -1,41 +1,163 @@
 use super::urlencoding;
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
-pub fn parse_query_params_and_urldecode(url: &str) -> (&str, HashMap<String, String>) {
+/// Decoded urlencoded parameters in the order they were sent.
+///
+/// A key can appear several times, e.g. `?tag=a&tag=b` or for checkboxes in a form.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QueryParams {
+    params: Vec<(String, String)>,
+}
+
+/// How urlencoded parameters are interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueryOptions {
+    /// Strips a trailing `[]` from keys, so `ids[]=1&ids[]=2` are two values of `ids`
+    pub bracket_arrays: bool,
+}
+
+pub fn parse_query_params_and_urldecode(url: &str) -> (&str, QueryParams) {
     let Some(qm_index) = url.char_indices().find_map(|(i, c)| match c {
         '?' => Some(i),
         _ => None,
     }) else {
-        return (url, HashMap::new());
+        return (url, QueryParams::default());
     };
 
     let (url, params) = url.split_at(qm_index);
 
     let params = params.strip_prefix('?').unwrap_or(params);
 
-    let params = params
-        .split('&')
-        .map(|pair| {
-            let idx = pair.char_indices().find_map(|(i, c)| match c {
-                '=' => Some(i),
-                _ => None,
-            });
-
-            if let Some(idx) = idx {
-                let (left, right) = pair.split_at(idx);
-                let right = right.strip_prefix('=').unwrap_or(right);
-                (left, right)
-            } else {
-                (pair, "")
-            }
-        })
-        .map(|(k, v)| (urlencoding::decode_string(k), urlencoding::decode_string(v)))
-        .filter_map(|kv| match kv {
-            (Some(k), Some(v)) => Some((k, v)),
-            _ => None,
-        })
-        .filter(|x| !x.0.is_empty())
-        .collect::<HashMap<_, _>>();
-
-    (url, params)
+    (url, QueryParams::parse(params))
+}
+
+/// Splits and decodes urlencoded pairs in order, a missing `=` means an empty value.
+///
+/// A pair that can't be decoded is returned as is in the error.
+pub fn decode_pairs(params: &str) -> impl Iterator<Item = Result<(String, String), &str>> {
+    params.split('&').map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        match (
+            urlencoding::decode_string(key),
+            urlencoding::decode_string(value),
+        ) {
+            (Some(key), Some(value)) => Ok((key, value)),
+            _ => Err(pair),
+        }
+    })
+}
+
+impl QueryParams {
+    /// Decodes an urlencoded string, pairs that can't be decoded are skipped
+    pub fn parse(params: &str) -> Self {
+        Self::parse_with(params, QueryOptions::default())
+    }
+
+    pub fn parse_with(params: &str, options: QueryOptions) -> Self {
+        decode_pairs(params)
+            .filter_map(Result::ok)
+            .map(|pair| options.apply(pair))
+            .filter(|(key, _)| !key.is_empty())
+            .collect()
+    }
+
+    /// Like [`parse_with`](Self::parse_with), but fails with the first pair that can't be decoded
+    pub fn try_parse_with(params: &str, options: QueryOptions) -> Result<Self, &str> {
+        decode_pairs(params)
+            .map(|pair| pair.map(|pair| options.apply(pair)))
+            // empty pairs, like from a trailing `&`, and nameless parameters are skipped
+            .filter(|pair| !matches!(pair, Ok((key, _)) if key.is_empty()))
+            .collect()
+    }
+
+    /// The first value of `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// All values of `key` in order
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.params
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parses the first value of `key`
+    pub fn get_as<T: FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.get(key).map(str::parse)
+    }
+
+    /// Parses all values of `key`, failing on the first one that can't be parsed
+    pub fn get_all_as<T: FromStr>(&self, key: &str) -> Result<Vec<T>, T::Err> {
+        self.get_all(key).map(str::parse).collect()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.params.iter().any(|(k, _)| k == key)
+    }
+
+    /// Adds a value after the existing ones
+    pub fn append(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.params.push((key.into(), value.into()));
+    }
+
+    /// Replaces all values of `key` with `value`
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let key = key.into();
+        self.remove(&key);
+        self.params.push((key, value.into()));
+    }
+
+    /// Removes all values of `key`
+    pub fn remove(&mut self, key: &str) {
+        self.params.retain(|(k, _)| k != key);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// One value per key, the last one wins for repeated keys
+    pub fn to_map(&self) -> HashMap<String, String> {
+        self.params.iter().cloned().collect()
+    }
+}
+
+impl QueryOptions {
+    fn apply(&self, (key, value): (String, String)) -> (String, String) {
+        match key.strip_suffix("[]") {
+            Some(stripped) if self.bracket_arrays => (stripped.to_owned(), value),
+            _ => (key, value),
+        }
+    }
+}
+
+impl FromIterator<(String, String)> for QueryParams {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        Self {
+            params: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<const N: usize> From<[(&str, &str); N]> for QueryParams {
+    fn from(value: [(&str, &str); N]) -> Self {
+        value
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
 }
@@ -1,6 +1,16 @@
 use core::str;
+use std::fmt::Write;
 
 pub fn decode_binary(input: &str) -> Option<Vec<u8>> {
+    decode(input, true)
+}
+
+/// Decodes only `%XX` escapes, a `+` stays a `+` like it should in paths
+pub fn percent_decode(input: &str) -> Option<Vec<u8>> {
+    decode(input, false)
+}
+
+fn decode(input: &str, plus_as_space: bool) -> Option<Vec<u8>> {
     let input = input.as_bytes();
 
     let mut output = Vec::new();
@@ -8,7 +18,7 @@ pub fn decode_binary(input: &str) -> Option<Vec<u8>> {
 
     while let Some(current) = input.get(i) {
         match *current {
-            b'+' => {
+            b'+' if plus_as_space => {
                 output.push(b' ');
                 i += 1;
                 continue;
@@ -23,7 +33,9 @@ pub fn decode_binary(input: &str) -> Option<Vec<u8>> {
             }
         }
 
-        let encoded = input.get(i + 1..i + 3)?;
+        let encoded = input
+            .get(i + 1..i + 3)
+            .filter(|encoded| encoded.iter().all(u8::is_ascii_hexdigit))?;
 
         let s = str::from_utf8(encoded)
             .expect("this came from a string so it should still be a valid string");
@@ -41,6 +53,55 @@ pub fn decode_string(input: &str) -> Option<String> {
     String::from_utf8(decode_binary(input)?).ok()
 }
 
+/// The parts of a URL, each allows a different set of characters unescaped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Userinfo,
+    Host,
+    /// A whole path, `/` separates segments and stays as is
+    Path,
+    /// A single path segment, a `/` in it is escaped
+    PathSegment,
+    Query,
+    /// A key or value inside the query, `&`, `=` and `+` are escaped
+    QueryParam,
+    Fragment,
+}
+
+impl Component {
+    pub(super) fn allows(self, byte: u8) -> bool {
+        let unreserved = byte.is_ascii_alphanumeric() || b"-._~".contains(&byte);
+        let sub_delim = b"!$&'()*+,;=".contains(&byte);
+        let pchar = unreserved || sub_delim || byte == b':' || byte == b'@';
+
+        match self {
+            Component::Userinfo => unreserved || sub_delim || byte == b':',
+            Component::Host => unreserved || sub_delim,
+            Component::Path => pchar || byte == b'/',
+            Component::PathSegment => pchar,
+            Component::Query | Component::Fragment => pchar || byte == b'/' || byte == b'?',
+            Component::QueryParam => {
+                (pchar || byte == b'/' || byte == b'?') && !b"&=+".contains(&byte)
+            }
+        }
+    }
+}
+
+/// Percent-encodes everything `component` doesn't allow, including `%` itself
+pub fn encode(input: &str, component: Component) -> String {
+    let mut output = String::with_capacity(input.len());
+
+    for byte in input.bytes() {
+        if component.allows(byte) {
+            output.push(byte as char);
+        } else {
+            write!(output, "%{byte:02X}").expect("writing to a String can't fail");
+        }
+    }
+
+    output
+}
+
 #[test]
 fn test_urldecode_string1() {
     let input = "fakten+lage";
@@ -1,5 +1,6 @@
 use crate::{
     http::{self, HttpRequest, HttpResponse, StatusCode},
+    transport::Transport,
     websocket::{
         base64,
         consts::headers::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE},
@@ -8,13 +9,13 @@ use crate::{
         WsConnection,
     },
 };
-use std::{collections::VecDeque, net::TcpStream};
+use std::collections::VecDeque;
 
 /// Initiates a websocket handshake on a stream, calling the specified handler when complete
-pub fn websocket_handshake(
+pub fn websocket_handshake<T: Transport>(
     req: &HttpRequest,
-    mut stream: TcpStream,
-) -> Result<WsConnection, http::Error> {
+    mut stream: T,
+) -> Result<WsConnection<T>, http::Error> {
     if req.headers.get(UPGRADE).map(|x| x.to_lowercase()) != Some("websocket".to_owned()) {
         return Err(http::Error::MissingOrInvalidWebsocketHeader { header: UPGRADE });
     }
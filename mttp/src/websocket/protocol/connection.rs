@@ -3,7 +3,7 @@ use super::{
     frame::{WebsocketFrame, WebsocketFrameRef},
     Close, CodeRange, OpCode, WebSocketMessage, WebSocketMessageRef,
 };
-use crate::websocket;
+use crate::{transport::Transport, websocket};
 use std::{
     borrow::{Borrow, Cow},
     collections::VecDeque,
@@ -12,8 +12,8 @@ use std::{
 
 #[derive(Debug)]
 /// Represents a Websocket connection to a client
-pub struct WsConnection {
-    stream: TcpStream,
+pub struct WsConnection<T: Transport = TcpStream> {
+    stream: T,
     message_buffer: VecDeque<WebSocketMessage>,
 }
 
@@ -25,8 +25,8 @@ pub enum TypeLock {
     None,
 }
 
-impl WsConnection {
-    pub(crate) fn new(stream: TcpStream, message_buffer: VecDeque<WebSocketMessage>) -> Self {
+impl<T: Transport> WsConnection<T> {
+    pub(crate) fn new(stream: T, message_buffer: VecDeque<WebSocketMessage>) -> Self {
         Self {
             stream,
             message_buffer,
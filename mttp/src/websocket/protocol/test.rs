@@ -1,5 +1,53 @@
 use super::frame::{WebsocketFrame, WebsocketFrameRef};
-use std::io::Cursor;
+use crate::{
+    transport::{Address, Transport},
+    websocket::{WebSocketMessage, WebSocketMessageRef, WsConnection},
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Cursor, Read, Write},
+    net::Shutdown,
+    sync::{Arc, Mutex},
+};
+
+/// In-memory transport that reads from a fixed input and records everything written
+struct Pipe {
+    input: Cursor<Vec<u8>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Pipe {
+    fn shutdown(&self, _: Shutdown) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Pipe {
+            input: self.input.clone(),
+            output: self.output.clone(),
+        })
+    }
+
+    fn peer_addr(&self) -> io::Result<Address> {
+        Ok(Address::Unknown)
+    }
+}
 
 #[test]
 fn test_frame_deser() {
@@ -69,3 +117,24 @@ fn test_frame_ser() {
 
     assert_eq!(stream.into_inner(), reference);
 }
+
+#[test]
+fn test_connection_over_custom_transport() {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let pipe = Pipe {
+        // masked text frame containing "Hi", key 0x01020304
+        input: Cursor::new(vec![0b10000001, 0b10000010, 1, 2, 3, 4, b'H' ^ 1, b'i' ^ 2]),
+        output: output.clone(),
+    };
+
+    let mut conn = WsConnection::new(pipe, VecDeque::new());
+
+    match conn.recv().unwrap() {
+        WebSocketMessage::Text(text) => assert_eq!(text, "Hi"),
+        other => panic!("Expected text message, got {other:?}"),
+    }
+
+    conn.send(&WebSocketMessageRef::Text("Ho")).unwrap();
+
+    assert_eq!(*output.lock().unwrap(), vec![0b10000001, 2, b'H', b'o']);
+}